@@ -54,18 +54,13 @@ impl std::ops::AddAssign for SmallBuf {
     }
 }
 
+// drive number 0 - 7
 #[derive(Debug, Copy, Clone)]
-enum DiskId {
-    D0,
-    D1,
-}
+struct DiskId(u8);
 
 impl DiskId {
     fn assemble(&self) -> u8 {
-        match self {
-            DiskId::D0 => 0,
-            DiskId::D1 => 1,
-        }
+        self.0
     }
 }
 
@@ -575,9 +570,8 @@ trait ParseArg<'a>: Sized {
 impl<'a> ParseArg<'a> for DiskId {
     fn parse(fragment: Fragment<'a>) -> Result<Self, Error<'a>> {
         let fragment = fragment.trim();
-        match fragment.as_str() {
-            "0" => Ok(DiskId::D0),
-            "1" => Ok(DiskId::D1),
+        match fragment.as_str().parse::<u8>() {
+            Ok(id) if id < 8 => Ok(DiskId(id)),
             _ => Err(Error {
                 fragment,
                 message: "invalid disk",
@@ -0,0 +1,441 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use tcpu::{DiskId, DiskMemory, Emulator, Memory, Registers, StopReason, Storage, DISK_SIZE};
+
+// how many cycles to run between checks for an interrupt from the debugger
+const CONTINUE_CHUNK: u64 = 10_000;
+
+// largest packet the debugger may send or receive, as told in `qSupported`
+const PACKET_SIZE: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_packet(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            // skip acks and interrupts received while stopped
+            while self.read_byte()? != b'$' {}
+            let mut packet = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    // a debugger keeps to the advertised size, give up on anything else
+                    _ if packet.len() == PACKET_SIZE => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "packet too large"));
+                    }
+                    byte => packet.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = packet.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if parse_hex(&checksum) == Some(u32::from(expected)) {
+                self.stream.write_all(b"+")?;
+                return Ok(packet);
+            } else {
+                self.stream.write_all(b"-")?;
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, checksum)?;
+        self.stream.flush()
+    }
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = match self.stream.read(&mut byte) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+}
+
+fn parse_hex(s: &[u8]) -> Option<u32> {
+    let s = std::str::from_utf8(s).ok()?;
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn parse_address_length(s: &[u8]) -> Option<(u16, usize)> {
+    let comma = s.iter().position(|&b| b == b',')?;
+    let address = u16::try_from(parse_hex(&s[..comma])?).ok()?;
+    let length = parse_hex(&s[(comma + 1)..])? as usize;
+    Some((address, length))
+}
+
+fn decode_hex_bytes(s: &[u8]) -> Option<Vec<u8>> {
+    if s.len() % 2 == 1 {
+        return None;
+    }
+    s.chunks(2).map(|pair| parse_hex(pair).map(|b| b as u8)).collect()
+}
+
+fn encode_word(value: u16) -> String {
+    let [low, high] = value.to_le_bytes();
+    format!("{:02x}{:02x}", low, high)
+}
+
+fn decode_word(s: &[u8]) -> Option<u16> {
+    match decode_hex_bytes(s)?.as_slice() {
+        &[low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+type Machine = Emulator<Memory, DiskMemory>;
+
+// register numbering used by the stub: a, b, c, d, i, j, p, s, ip
+const REGISTER_COUNT: usize = 9;
+
+fn read_register(emulator: &Machine, index: usize) -> Option<u16> {
+    let r = emulator.registers();
+    Some(match index {
+        0 => r.a,
+        1 => r.b,
+        2 => r.c,
+        3 => r.d,
+        4 => r.i,
+        5 => r.j,
+        6 => r.p,
+        7 => r.s,
+        8 => emulator.instruction_pointer(),
+        _ => return None,
+    })
+}
+
+fn write_register(emulator: &mut Machine, index: usize, value: u16) -> Option<()> {
+    let mut r: Registers = emulator.registers();
+    match index {
+        0 => r.a = value,
+        1 => r.b = value,
+        2 => r.c = value,
+        3 => r.d = value,
+        4 => r.i = value,
+        5 => r.j = value,
+        6 => r.p = value,
+        7 => r.s = value,
+        8 => {
+            emulator.set_instruction_pointer(value);
+            return Some(());
+        }
+        _ => return None,
+    }
+    emulator.set_registers(r);
+    Some(())
+}
+
+fn stop_reply(emulator: &Machine, signal: u8) -> String {
+    if emulator.is_running() {
+        format!("S{:02x}", signal)
+    } else {
+        "W00".to_owned()
+    }
+}
+
+enum Next {
+    Continue,
+    Close,
+}
+
+fn resume(connection: &mut Connection, emulator: &mut Machine) -> io::Result<String> {
+    loop {
+        match emulator.run_until(CONTINUE_CHUNK) {
+            StopReason::Halted => return Ok("W00".to_owned()),
+            StopReason::Breakpoint(_) => return Ok(format!("T{:02x}swbreak:;", SIGTRAP)),
+            StopReason::Watchpoint { .. } => return Ok(stop_reply(emulator, SIGTRAP)),
+            StopReason::Waiting | StopReason::BudgetExhausted => {
+                if connection.interrupted()? {
+                    return Ok(stop_reply(emulator, SIGINT));
+                }
+            }
+        }
+    }
+}
+
+fn handle(connection: &mut Connection, emulator: &mut Machine, packet: &[u8]) -> io::Result<Next> {
+    let (&command, args) = match packet.split_first() {
+        Some(split) => split,
+        None => {
+            connection.send_packet("")?;
+            return Ok(Next::Continue);
+        }
+    };
+    let reply = match command {
+        b'?' => stop_reply(emulator, SIGTRAP),
+        b'g' => (0..REGISTER_COUNT)
+            .map(|index| encode_word(read_register(emulator, index).unwrap()))
+            .collect(),
+        b'G' => {
+            if args.len() != REGISTER_COUNT * 4 {
+                "E01".to_owned()
+            } else {
+                match args.chunks(4).map(decode_word).collect::<Option<Vec<_>>>() {
+                    Some(values) => {
+                        for (index, value) in values.into_iter().enumerate() {
+                            write_register(emulator, index, value);
+                        }
+                        "OK".to_owned()
+                    }
+                    None => "E01".to_owned(),
+                }
+            }
+        }
+        b'p' => match parse_hex(args).and_then(|index| read_register(emulator, index as usize)) {
+            Some(value) => encode_word(value),
+            None => "E01".to_owned(),
+        },
+        b'P' => {
+            let parsed = args.iter().position(|&b| b == b'=').and_then(|eq| {
+                let index = parse_hex(&args[..eq])? as usize;
+                let value = decode_word(&args[(eq + 1)..])?;
+                write_register(emulator, index, value)
+            });
+            match parsed {
+                Some(()) => "OK".to_owned(),
+                None => "E01".to_owned(),
+            }
+        }
+        b'm' => match parse_address_length(args) {
+            // every byte takes two hex digits in the reply
+            Some((address, length)) if length <= PACKET_SIZE / 2 => (0..length)
+                .map(|offset| {
+                    let address = address.wrapping_add(offset as u16);
+                    format!("{:02x}", emulator.memory()[usize::from(address)])
+                })
+                .collect(),
+            _ => "E01".to_owned(),
+        },
+        b'M' => {
+            let parsed = args.iter().position(|&b| b == b':').and_then(|colon| {
+                let (address, length) = parse_address_length(&args[..colon])?;
+                let bytes = decode_hex_bytes(&args[(colon + 1)..])?;
+                if bytes.len() == length { Some((address, bytes)) } else { None }
+            });
+            match parsed {
+                Some((address, bytes)) => {
+                    for (offset, byte) in bytes.into_iter().enumerate() {
+                        let address = address.wrapping_add(offset as u16);
+                        emulator.memory_mut()[usize::from(address)] = byte;
+                    }
+                    "OK".to_owned()
+                }
+                None => "E01".to_owned(),
+            }
+        }
+        b's' => {
+            emulator.cycle();
+            stop_reply(emulator, SIGTRAP)
+        }
+        b'c' => resume(connection, emulator)?,
+        b'Z' | b'z' => {
+            let mut parts = args.split(|&b| b == b',');
+            let kind = parts.next();
+            let address = parts.next().and_then(parse_hex).and_then(|a| u16::try_from(a).ok());
+            match (kind, address) {
+                (Some(b"0"), Some(address)) => {
+                    if command == b'Z' {
+                        emulator.add_breakpoint(address);
+                    } else {
+                        emulator.remove_breakpoint(address);
+                    }
+                    "OK".to_owned()
+                }
+                // only software breakpoints are supported
+                _ => String::new(),
+            }
+        }
+        b'q' if args.starts_with(b"Supported") => format!("PacketSize={:x};swbreak+", PACKET_SIZE),
+        b'q' if args == b"Attached" => "1".to_owned(),
+        b'D' => {
+            connection.send_packet("OK")?;
+            return Ok(Next::Close);
+        }
+        b'k' => return Ok(Next::Close),
+        _ => String::new(),
+    };
+    connection.send_packet(&reply)?;
+    Ok(Next::Continue)
+}
+
+fn serve(stream: TcpStream, emulator: &mut Machine) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut connection = Connection { stream };
+    loop {
+        let packet = connection.read_packet()?;
+        if let Next::Close = handle(&mut connection, emulator, &packet)? {
+            return Ok(());
+        }
+    }
+}
+
+fn load_disk(emulator: &mut Machine, id: DiskId, path: &str) {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("failed to read {}:\n  {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    if data.len() > DISK_SIZE {
+        eprintln!("disk image {} is larger than {} bytes", path, DISK_SIZE);
+        std::process::exit(1);
+    }
+    emulator.disk_slot(id).as_mut()[..data.len()].copy_from_slice(&data);
+    emulator.insert_disk(id);
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("usage: {} <port> <disk0> [disk1]", args[0]);
+        eprintln!("    registers are reported in order a, b, c, d, i, j, p, s, ip");
+        std::process::exit(2);
+    }
+    let port = match args[1].parse::<u16>() {
+        Ok(port) => port,
+        Err(_) => {
+            eprintln!("invalid port: {}", args[1]);
+            std::process::exit(2);
+        }
+    };
+
+    let mut emulator = Machine::new();
+    load_disk(&mut emulator, DiskId::D0, &args[2]);
+    if let Some(path) = args.get(3) {
+        load_disk(&mut emulator, DiskId::D1, path);
+    }
+    emulator.reset();
+
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to listen on port {}:\n  {}", port, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("waiting for debugger on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve(stream, &mut emulator));
+        match result {
+            Ok(()) => eprintln!("debugger detached"),
+            Err(e) => eprintln!("connection closed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sends `packet` to `handle` and returns the reply without framing
+    fn request(emulator: &mut Machine, packet: &str) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut connection = Connection { stream: listener.accept().unwrap().0 };
+        handle(&mut connection, emulator, packet.as_bytes()).unwrap();
+        drop(connection);
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with('$'), "unframed reply {:?}", reply);
+        reply[1..(reply.len() - 3)].to_owned()
+    }
+
+    #[test]
+    fn packet_framing() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut connection = Connection { stream: listener.accept().unwrap().0 };
+        client.write_all(b"+$g#67").unwrap();
+        assert_eq!(connection.read_packet().unwrap(), b"g");
+
+        let largest = vec![b'0'; PACKET_SIZE];
+        write!(client, "$").unwrap();
+        client.write_all(&largest).unwrap();
+        write!(client, "#{:02x}", (b'0' as usize * PACKET_SIZE) % 0x100).unwrap();
+        assert_eq!(connection.read_packet().unwrap(), largest);
+
+        write!(client, "$").unwrap();
+        client.write_all(&vec![b'0'; PACKET_SIZE + 1]).unwrap();
+        let error = connection.read_packet().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parse_packet_fields() {
+        assert_eq!(parse_hex(b"1f"), Some(0x1f));
+        assert_eq!(parse_hex(b"x"), None);
+        assert_eq!(parse_address_length(b"2000,10"), Some((0x2000, 0x10)));
+        assert_eq!(parse_address_length(b"10000,1"), None);
+        assert_eq!(parse_address_length(b"2000"), None);
+        assert_eq!(decode_hex_bytes(b"00ff7a"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex_bytes(b"0ff"), None);
+        assert_eq!(decode_hex_bytes(b"zz"), None);
+        assert_eq!(decode_word(b"3412"), Some(0x1234));
+        assert_eq!(decode_word(b"34"), None);
+        assert_eq!(encode_word(0x1234), "3412");
+    }
+
+    #[test]
+    fn registers() {
+        let mut emulator = Machine::new();
+        let values = "0100020003000400050006000700080009a0";
+        assert_eq!(request(&mut emulator, &format!("G{}", values)), "OK");
+        assert_eq!(request(&mut emulator, "g"), values);
+        assert_eq!(request(&mut emulator, "p8"), "09a0");
+        assert_eq!(request(&mut emulator, "P1=ffff"), "OK");
+        assert_eq!(emulator.registers().b, 0xffff);
+        assert_eq!(request(&mut emulator, "p9"), "E01");
+
+        // nothing is written if any register doesn't decode
+        let invalid = "000000000000000000000000000000000zz0";
+        assert_eq!(request(&mut emulator, &format!("G{}", invalid)), "E01");
+        assert_eq!(emulator.registers().a, 1);
+        assert_eq!(request(&mut emulator, "G0000"), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut emulator = Machine::new();
+        assert_eq!(request(&mut emulator, "Mfffe,3:0a0b0c"), "OK");
+        assert_eq!(emulator.memory()[0xffff], 0x0b);
+        assert_eq!(emulator.memory()[0], 0x0c);
+        assert_eq!(request(&mut emulator, "mfffe,3"), "0a0b0c");
+        assert_eq!(request(&mut emulator, "M0,2:0a"), "E01");
+
+        let reply = request(&mut emulator, &format!("m0,{:x}", PACKET_SIZE / 2));
+        assert_eq!(reply.len(), PACKET_SIZE);
+        assert_eq!(request(&mut emulator, &format!("m0,{:x}", PACKET_SIZE / 2 + 1)), "E01");
+        assert_eq!(request(&mut emulator, "m0,ffffffff"), "E01");
+    }
+
+    #[test]
+    fn breakpoints_and_queries() {
+        let mut emulator = Machine::new();
+        assert_eq!(request(&mut emulator, "qSupported:swbreak+"), "PacketSize=1000;swbreak+");
+        assert_eq!(request(&mut emulator, "Z0,4,1"), "OK");
+        assert_eq!(request(&mut emulator, "Z1,4,1"), "");
+        assert_eq!(request(&mut emulator, "c"), format!("T{:02x}swbreak:;", SIGTRAP));
+        assert_eq!(emulator.instruction_pointer(), 4);
+        assert_eq!(request(&mut emulator, "z0,4,1"), "OK");
+        assert_eq!(request(&mut emulator, "s"), format!("S{:02x}", SIGTRAP));
+        assert_eq!(request(&mut emulator, "vMustReplyEmpty"), "");
+    }
+}
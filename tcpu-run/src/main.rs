@@ -0,0 +1,232 @@
+use std::cmp;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::ops::Range;
+use tcpu::{DiskId, DiskImage, DiskMemory, Emulator, MachineConfig, Memory, ScreenCapture, Storage, CONSOLE_BUFFER_SIZE};
+
+// console output is flushed between runs of this many cycles, every store
+// takes at least a cycle so the console buffer can't overflow in between
+const CHUNK: u64 = CONSOLE_BUFFER_SIZE as u64;
+
+const DEFAULT_CYCLES: u64 = 100_000_000;
+
+const EXIT_HALTED: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
+
+const DISK_IDS: [DiskId; 2] = [DiskId::D0, DiskId::D1];
+
+type Machine = Emulator<Memory, DiskMemory, ScreenCapture>;
+
+struct Options {
+    cycles: u64,
+    disks: Vec<String>,
+    screen: Option<String>,
+    memory: Option<String>,
+    disk_outputs: [Option<String>; 2],
+    png: Option<String>,
+    gif: Option<String>,
+    frames: Range<u64>,
+    scale: u16,
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("usage: {} [options] <disk0> [disk1]", program);
+    eprintln!("    disks are raw or tcpu disk images, outputs are raw");
+    eprintln!("    --cycles <n>        stop after n cycles, default {}", DEFAULT_CYCLES);
+    eprintln!("    --screen <file>     write the final screen, one byte per pixel");
+    eprintln!("    --memory <file>     write the final memory");
+    eprintln!("    --disk0-out <file>  write disk 0 if it was modified, same for --disk1-out");
+    eprintln!("    --png <file>        write the last captured frame as an image");
+    eprintln!("    --gif <file>        write the captured frames as an animation");
+    eprintln!("    --frames <range>    screen refreshes to capture, counting from 0, as");
+    eprintln!("                        <n>, <first>..<end> or <first>.., default all");
+    eprintln!("    --scale <n>         scale images and animations up n times, default 1");
+    eprintln!("exits with 0 if the cpu halted, 3 if it ran out of cycles and 1 on errors");
+    std::process::exit(EXIT_USAGE);
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        cycles: DEFAULT_CYCLES,
+        disks: Vec::new(),
+        screen: None,
+        memory: None,
+        disk_outputs: [None, None],
+        png: None,
+        gif: None,
+        frames: 0..u64::MAX,
+        scale: 1,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.disks.push(arg.clone());
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?.clone();
+        match arg.as_str() {
+            "--cycles" => {
+                options.cycles = value.parse().map_err(|_| format!("invalid cycle count: {}", value))?;
+            }
+            "--screen" => options.screen = Some(value),
+            "--memory" => options.memory = Some(value),
+            "--disk0-out" => options.disk_outputs[0] = Some(value),
+            "--disk1-out" => options.disk_outputs[1] = Some(value),
+            "--png" => options.png = Some(value),
+            "--gif" => options.gif = Some(value),
+            "--frames" => {
+                options.frames = parse_frames(&value).ok_or_else(|| format!("invalid frame range: {}", value))?;
+            }
+            "--scale" => {
+                options.scale = match value.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("invalid scale: {}", value)),
+                };
+            }
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    if options.disks.is_empty() || options.disks.len() > DISK_IDS.len() {
+        return Err("expected one or two disk images".to_owned());
+    }
+    if options.disk_outputs[options.disks.len()..].iter().any(Option::is_some) {
+        return Err("disk output given for a drive without a disk".to_owned());
+    }
+    Ok(options)
+}
+
+fn parse_frames(s: &str) -> Option<Range<u64>> {
+    match s.find("..") {
+        Some(dots) => {
+            let start = s[..dots].parse().ok()?;
+            let end = match &s[(dots + 2)..] {
+                "" => u64::MAX,
+                end => end.parse().ok()?,
+            };
+            Some(start..end)
+        }
+        None => {
+            let frame = s.parse::<u64>().ok()?;
+            Some(frame..frame.checked_add(1)?)
+        }
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_ERROR);
+}
+
+fn load_disk(emulator: &mut Machine, id: DiskId, path: &str) {
+    let data = std::fs::read(path).unwrap_or_else(|e| fail(format!("failed to read {}:\n  {}", path, e)));
+    let image = DiskImage::load(&data).unwrap_or_else(|e| fail(format!("failed to load {}:\n  {}", path, e)));
+    emulator.disk_slot(id).as_mut().copy_from_slice(image.data());
+    if image.read_only {
+        emulator.insert_read_only_disk(id);
+    } else {
+        emulator.insert_disk(id);
+    }
+}
+
+fn write_file(path: &str, data: &[u8]) {
+    if let Err(e) = std::fs::write(path, data) {
+        fail(format!("failed to write {}:\n  {}", path, e));
+    }
+}
+
+fn write_image(path: &str, encode: impl FnOnce(&mut io::BufWriter<std::fs::File>) -> io::Result<()>) {
+    let result = std::fs::File::create(path).and_then(|file| {
+        let mut out = io::BufWriter::new(file);
+        encode(&mut out)?;
+        out.flush()
+    });
+    if let Err(e) = result {
+        fail(format!("failed to write {}:\n  {}", path, e));
+    }
+}
+
+// time between screen refreshes in hundredths of a second, as gif frame delays are
+fn frame_delay(config: MachineConfig) -> u16 {
+    let delay = (config.screen_refresh_time * 100 + config.clock_rate / 2) / config.clock_rate;
+    u16::try_from(cmp::max(delay, 1)).unwrap_or(u16::MAX)
+}
+
+/// Runs until the cpu halts or `cycles` run out, copying console output
+/// to stdout. Returns whether the cpu halted.
+fn run(emulator: &mut Machine, cycles: u64) -> io::Result<bool> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut remaining = cycles;
+    while emulator.is_running() && remaining > 0 {
+        let chunk = cmp::min(remaining, CHUNK);
+        emulator.run(chunk);
+        remaining -= chunk;
+        stdout.write_all(emulator.console_output())?;
+        emulator.clear_console_output();
+    }
+    stdout.flush()?;
+    Ok(!emulator.is_running())
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let options = match parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            usage(&args[0]);
+        }
+    };
+
+    // frames are only kept for the images that were asked for
+    let capture = if options.gif.is_some() {
+        ScreenCapture::new(options.frames.clone())
+    } else if options.png.is_some() {
+        ScreenCapture::last_frame(options.frames.clone())
+    } else {
+        ScreenCapture::new(0..0)
+    };
+    let mut emulator = Machine::with_tracer(Default::default(), Default::default(), capture);
+    for (&id, path) in DISK_IDS.iter().zip(&options.disks) {
+        load_disk(&mut emulator, id, path);
+    }
+    emulator.reset();
+
+    let halted = run(&mut emulator, options.cycles)
+        .unwrap_or_else(|e| fail(format!("failed to write console output:\n  {}", e)));
+
+    if let Some(path) = &options.screen {
+        let pixels = emulator.screen().iter().flatten().copied().collect::<Vec<_>>();
+        write_file(path, &pixels);
+    }
+    if let Some(path) = &options.memory {
+        write_file(path, &emulator.memory()[..]);
+    }
+    let frames = emulator.tracer().take_frames();
+    if let Some(path) = &options.png {
+        let frame = frames.last().unwrap_or_else(|| fail("no frames were captured".to_owned()));
+        write_image(path, |out| tcpu::write_png(frame, options.scale, out));
+    }
+    if let Some(path) = &options.gif {
+        if frames.is_empty() {
+            fail("no frames were captured".to_owned());
+        }
+        let delay = frame_delay(emulator.config());
+        write_image(path, |out| tcpu::write_gif(&frames, options.scale, delay, out));
+    }
+    for (&id, path) in DISK_IDS.iter().zip(&options.disk_outputs) {
+        if let (Some(path), Some(disk)) = (path, emulator.disk(id)) {
+            if disk.modified {
+                write_file(path, &disk.data[..]);
+            }
+        }
+    }
+
+    if !halted {
+        eprintln!("cpu still running after {} cycles", options.cycles);
+        std::process::exit(EXIT_TIMEOUT);
+    }
+    std::process::exit(EXIT_HALTED);
+}
@@ -0,0 +1,275 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use termion::{clear, color, cursor, style};
+use tcpu::palette;
+use tcpu::{DiskEncoding, DiskId, DiskImage, DiskImageError, DiskMemory, Emulator, Memory, Storage, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const FRAME_TIME: Duration = Duration::from_millis(1000 / 60);
+
+// don't try to catch up on time the process spent suspended
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+// terminals only report key presses, so a key is released once it hasn't
+// been repeated for a while, the first repeat comes later than the others
+const FIRST_REPEAT_DELAY: Duration = Duration::from_millis(550);
+const REPEAT_DELAY: Duration = Duration::from_millis(100);
+
+const DISK_IDS: [DiskId; 2] = [DiskId::D0, DiskId::D1];
+
+type Machine = Emulator<Memory, DiskMemory>;
+
+struct HeldKey {
+    code: u16,
+    release_time: Instant,
+}
+
+struct Frontend {
+    emulator: Machine,
+    held_keys: Vec<HeldKey>,
+    time_budget: f64,
+    drawn_screen: Option<[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]>,
+}
+
+impl Frontend {
+    fn key_pressed(&mut self, code: u16, now: Instant) {
+        if let Some(held) = self.held_keys.iter_mut().find(|held| held.code == code) {
+            held.release_time = now + REPEAT_DELAY;
+        } else {
+            self.held_keys.push(HeldKey {
+                code,
+                release_time: now + FIRST_REPEAT_DELAY,
+            });
+            self.emulator.key_down(code);
+        }
+    }
+
+    fn release_keys(&mut self, now: Instant) {
+        let emulator = &mut self.emulator;
+        self.held_keys.retain(|held| {
+            if held.release_time > now {
+                return true;
+            }
+            emulator.key_up(held.code);
+            false
+        });
+    }
+
+    fn reset(&mut self) {
+        for held in self.held_keys.drain(..) {
+            self.emulator.key_up(held.code);
+        }
+        self.emulator.reset();
+    }
+
+    /// Runs the emulator for `dt` of real time.
+    fn run(&mut self, dt: Duration) {
+        let clock_rate = self.emulator.config().clock_rate as f64;
+        self.time_budget += dt.as_secs_f64();
+        let cycles = (self.time_budget * clock_rate).floor();
+        self.time_budget -= cycles / clock_rate;
+        self.emulator.run(cycles as u64);
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let screen = self.emulator.screen();
+        if self.drawn_screen.as_ref() == Some(screen) {
+            return Ok(());
+        }
+        let mut frame = String::new();
+        write!(frame, "{}", cursor::Goto(1, 1)).unwrap();
+        // every cell shows two pixels, the top one in the foreground
+        for rows in screen.chunks(2) {
+            for (&top, &bottom) in rows[0].iter().zip(&rows[1]) {
+                let (tr, tg, tb) = palette::color(top);
+                let (br, bg, bb) = palette::color(bottom);
+                write!(frame, "{}{}\u{2580}", color::Fg(color::Rgb(tr, tg, tb)), color::Bg(color::Rgb(br, bg, bb))).unwrap();
+            }
+            write!(frame, "{}\r\n", style::Reset).unwrap();
+        }
+        let state = if self.emulator.is_running() { "running" } else { "halted" };
+        write!(frame, "{}{}  ctrl-r: reset  ctrl-c: quit", clear::CurrentLine, state).unwrap();
+        self.drawn_screen = Some(*screen);
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// Key code the web frontend would send for the key, its `KeyboardEvent.which`.
+fn key_code(key: Key) -> Option<u16> {
+    Some(match key {
+        Key::Backspace => 8,
+        Key::Char('\t') => 9,
+        Key::Char('\n') => 13,
+        Key::Esc => 27,
+        Key::Char(' ') => 32,
+        Key::PageUp => 33,
+        Key::PageDown => 34,
+        Key::End => 35,
+        Key::Home => 36,
+        Key::Left => 37,
+        Key::Up => 38,
+        Key::Right => 39,
+        Key::Down => 40,
+        Key::Insert => 45,
+        Key::Delete => 46,
+        Key::F(n @ 1..=12) => 111 + u16::from(n),
+        Key::Char(c @ '0'..='9') | Key::Char(c @ 'A'..='Z') => c as u16,
+        Key::Char(c @ 'a'..='z') => c.to_ascii_uppercase() as u16,
+        // shifted characters are reported by the key they're on
+        Key::Char(c) => match c {
+            ')' => 48,
+            '!' => 49,
+            '@' => 50,
+            '#' => 51,
+            '$' => 52,
+            '%' => 53,
+            '^' => 54,
+            '&' => 55,
+            '*' => 56,
+            '(' => 57,
+            ';' | ':' => 186,
+            '=' | '+' => 187,
+            ',' | '<' => 188,
+            '-' | '_' => 189,
+            '.' | '>' => 190,
+            '/' | '?' => 191,
+            '`' | '~' => 192,
+            '[' | '{' => 219,
+            '\\' | '|' => 220,
+            ']' | '}' => 221,
+            '\'' | '"' => 222,
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+struct LoadedDisk {
+    path: String,
+    // set for tcpu disk images, raw images are written back raw
+    label: Option<String>,
+}
+
+fn load_disk(emulator: &mut Machine, id: DiskId, path: &str) -> LoadedDisk {
+    let loaded = std::fs::read(path).map_err(|e| e.to_string()).and_then(|data| {
+        let image = match DiskImage::decode(&data) {
+            Err(DiskImageError::BadMagic) => DiskImage::from_raw(&data).map(|image| (image, false)),
+            result => result.map(|image| (image, true)),
+        };
+        image.map_err(|e| e.to_string())
+    });
+    let (image, is_container) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("failed to load {}:\n  {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    emulator.disk_slot(id).as_mut().copy_from_slice(image.data());
+    if image.read_only {
+        emulator.insert_read_only_disk(id);
+    } else {
+        emulator.insert_disk(id);
+    }
+    LoadedDisk {
+        path: path.to_owned(),
+        label: if is_container { Some(image.label) } else { None },
+    }
+}
+
+fn save_disks(emulator: &mut Machine, disks: &[LoadedDisk]) -> bool {
+    let mut all_ok = true;
+    for (&id, loaded) in DISK_IDS.iter().zip(disks) {
+        if let Some(disk) = emulator.disk(id) {
+            if disk.modified {
+                let result = match &loaded.label {
+                    Some(label) => {
+                        let mut image = DiskImage::new(label);
+                        image.data_mut().copy_from_slice(&disk.data[..]);
+                        std::fs::write(&loaded.path, image.encode(DiskEncoding::Sparse))
+                    }
+                    None => std::fs::write(&loaded.path, &disk.data[..]),
+                };
+                if let Err(e) = result {
+                    eprintln!("failed to write {}:\n  {}", loaded.path, e);
+                    all_ok = false;
+                }
+            }
+        }
+    }
+    all_ok
+}
+
+fn run(frontend: &mut Frontend) -> io::Result<()> {
+    let stdout = io::stdout().into_raw_mode()?;
+    let mut stdout = AlternateScreen::from(stdout);
+    write!(stdout, "{}{}", cursor::Hide, clear::All)?;
+    let mut keys = termion::async_stdin().keys();
+    let mut last_time = Instant::now();
+    let result = 'frames: loop {
+        let now = Instant::now();
+        for key in &mut keys {
+            match key {
+                Ok(Key::Ctrl('c')) => break 'frames Ok(()),
+                Ok(Key::Ctrl('r')) => frontend.reset(),
+                Ok(key) => {
+                    if let Some(code) = key_code(key) {
+                        frontend.key_pressed(code, now);
+                    }
+                }
+                Err(e) => break 'frames Err(e),
+            }
+        }
+        frontend.release_keys(now);
+        frontend.run(std::cmp::min(now - last_time, MAX_FRAME_TIME));
+        last_time = now;
+        if let Err(e) = frontend.draw(&mut stdout) {
+            break Err(e);
+        }
+        let elapsed = now.elapsed();
+        if elapsed < FRAME_TIME {
+            thread::sleep(FRAME_TIME - elapsed);
+        }
+    };
+    write!(stdout, "{}{}", style::Reset, cursor::Show)?;
+    stdout.flush()?;
+    result
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <disk0> [disk1]", args[0]);
+        eprintln!("    disks are raw or tcpu disk images, modified disks are");
+        eprintln!("    written back in the same format when quitting with ctrl-c");
+        std::process::exit(2);
+    }
+    let mut emulator = Machine::new();
+    let disks = DISK_IDS.iter()
+        .zip(&args[1..])
+        .map(|(&id, path)| load_disk(&mut emulator, id, path))
+        .collect::<Vec<_>>();
+    emulator.reset();
+
+    let mut frontend = Frontend {
+        emulator,
+        held_keys: Vec::new(),
+        time_budget: 0.0,
+        drawn_screen: None,
+    };
+    let result = run(&mut frontend);
+    let saved = save_disks(&mut frontend.emulator, &disks);
+    if let Err(e) = result {
+        eprintln!("terminal error:\n  {}", e);
+        std::process::exit(1);
+    }
+    if !saved {
+        std::process::exit(1);
+    }
+}
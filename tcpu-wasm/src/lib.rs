@@ -8,7 +8,7 @@ use alloc::Box;
 use core::convert::TryInto;
 use core::fmt;
 use core::mem::MaybeUninit;
-use tcpu::{Disk, DiskId, Emulator, Storage, DISK_SIZE, MEMORY_SIZE};
+use tcpu::{Disk, DiskId, Emulator, MachineConfig, NoDevice, NoopTracer, Storage, DISK_SIZE, MEMORY_SIZE};
 
 impl Default for Box<[u8; tcpu::MEMORY_SIZE]> {
     fn default() -> Self {
@@ -43,6 +43,9 @@ fn abort(message: &str) -> ! {
     unsafe { core::arch::wasm32::unreachable() }
 }
 
+// as many drives as the web frontend shows, every drive takes 1 MiB
+const DISK_DRIVES: usize = 2;
+
 struct RuntimeData {
     emulator: Emulator<Box<[u8; MEMORY_SIZE]>, Box<[u8; DISK_SIZE]>>,
 }
@@ -53,7 +56,13 @@ static mut DATA: MaybeUninit<RuntimeData> = MaybeUninit::uninit();
 pub extern fn initialize() {
     unsafe {
         DATA = MaybeUninit::new(RuntimeData {
-            emulator: Emulator::new(),
+            emulator: Emulator::with_config(
+                MachineConfig::classic(),
+                Default::default(),
+                [(); DISK_DRIVES].map(|_| Default::default()),
+                NoopTracer,
+                NoDevice,
+            ),
         });
     }
 }
@@ -62,6 +71,13 @@ unsafe fn get_runtime_data() -> &'static mut RuntimeData {
     &mut *DATA.as_mut_ptr()
 }
 
+fn disk_id(data: &RuntimeData, id: u32) -> DiskId {
+    match id.try_into() {
+        Ok(disk_id) if (id as usize) < data.emulator.disk_drives() => disk_id,
+        _ => abort("invalid disk id"),
+    }
+}
+
 macro_rules! export {
     ($(fn $name:ident($($data:ident: &mut RuntimeData $(, $($args:tt)*)?)?) $(-> $ret:ty)? $body:block)*) => {
         $(const _: () = {
@@ -77,6 +93,7 @@ macro_rules! export {
 struct DiskStats {
     present: bool,
     modified: bool,
+    read_only: bool,
     idle_time: u32,
 }
 
@@ -85,13 +102,15 @@ impl DiskStats {
         DiskStats {
             present: false,
             modified: false,
+            read_only: false,
             idle_time: 0,
         }
     }
     
     fn as_bits(&self) -> u32 {
         // js runtime interprets return type as i32
-        (core::cmp::min(self.idle_time, u32::max_value() >> 3) << 2) |
+        (core::cmp::min(self.idle_time, u32::max_value() >> 4) << 3) |
+        (u32::from(self.read_only) << 2) |
         (u32::from(self.modified) << 1) |
         u32::from(self.present)
     }
@@ -106,6 +125,10 @@ export! {
         data.emulator.reset();
     }
 
+    fn clock_rate(data: &mut RuntimeData) -> u32 {
+        data.emulator.config().clock_rate as u32
+    }
+
     fn screen_buffer(data: &mut RuntimeData) -> *const u8 {
         data.emulator.screen().as_ptr() as *const u8
     }
@@ -118,16 +141,41 @@ export! {
         tcpu::SCREEN_HEIGHT as u32
     }
 
+    fn audio_buffer(data: &mut RuntimeData) -> *const i16 {
+        data.emulator.audio_samples().as_ptr()
+    }
+
+    fn audio_samples(data: &mut RuntimeData) -> u32 {
+        data.emulator.audio_samples().len() as u32
+    }
+
+    fn clear_audio(data: &mut RuntimeData) {
+        data.emulator.clear_audio_samples();
+    }
+
+    fn audio_cycles_per_sample() -> u32 {
+        tcpu::CYCLES_PER_SAMPLE as u32
+    }
+
+    fn events_lost(data: &mut RuntimeData) -> u32 {
+        data.emulator.events_lost() as u32
+    }
+
     fn is_running(data: &mut RuntimeData) -> bool {
         data.emulator.is_running()
     }
 
+    fn disk_drives(data: &mut RuntimeData) -> u32 {
+        data.emulator.disk_drives() as u32
+    }
+
     fn disk_stats(data: &mut RuntimeData, id: u32) -> u32 {
-        let disk_id = id.try_into().unwrap_or_else(|_| abort("invalid disk id"));
+        let disk_id = disk_id(data, id);
         let stats = if let Some(disk) = data.emulator.disk(disk_id) {
             DiskStats {
                 present: true,
                 modified: disk.modified,
+                read_only: disk.read_only,
                 idle_time: disk.idle_time.try_into().unwrap_or(u32::max_value()),
             }
         } else {
@@ -138,17 +186,22 @@ export! {
     }
 
     fn disk_buffer(data: &mut RuntimeData, id: u32) -> *mut u8 {
-        let disk_id = id.try_into().unwrap_or_else(|_| abort("invalid disk id"));
+        let disk_id = disk_id(data, id);
         data.emulator.disk_slot(disk_id).as_mut_ptr()
     }
 
     fn insert_disk(data: &mut RuntimeData, id: u32) {
-        let disk_id = id.try_into().unwrap_or_else(|_| abort("invalid disk id"));
+        let disk_id = disk_id(data, id);
         data.emulator.insert_disk(disk_id);
     }
 
+    fn insert_read_only_disk(data: &mut RuntimeData, id: u32) {
+        let disk_id = disk_id(data, id);
+        data.emulator.insert_read_only_disk(disk_id);
+    }
+
     fn remove_disk(data: &mut RuntimeData, id: u32) {
-        let disk_id = id.try_into().unwrap_or_else(|_| abort("invalid disk id"));
+        let disk_id = disk_id(data, id);
         data.emulator.remove_disk(disk_id);
     }
 
@@ -15,6 +15,32 @@ fn criterion_benchmark(c: &mut Criterion) {
         let emulator = black_box(&mut emulator);
         emulator.run(100_000);
     }));
+    c.bench_function("run 1e7 waiting cycles", |b| b.iter(|| {
+        let mut emulator = emulator();
+        emulator.memory_mut()[..3].copy_from_slice(&[
+            0x02, // wait
+            0x5d, 0x00, // jmp 0
+        ]);
+        let emulator = black_box(&mut emulator);
+        emulator.run(10_000_000);
+    }));
+    for &cached in &[false, true] {
+        let name = if cached { "run 1e6 loop cycles with instruction cache" } else { "run 1e6 loop cycles" };
+        c.bench_function(name, |b| b.iter(|| {
+            let mut emulator = emulator();
+            emulator.memory_mut()[..9].copy_from_slice(&[
+                0x80, 0x4e, 0x34, 0x12, // mov i, 0x1234
+                0x81, 0x04, // add a, i
+                0x83, 0x10, // xor b, a
+                0x58, // jmp 0
+            ]);
+            if cached {
+                emulator.enable_instruction_cache();
+            }
+            let emulator = black_box(&mut emulator);
+            emulator.run(1_000_000);
+        }));
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
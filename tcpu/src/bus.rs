@@ -0,0 +1,171 @@
+use super::{Event, Events, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// A peripheral mapped into the address space. Loads and stores the CPU
+/// makes to addresses the device `contains` are routed to it, by default
+/// they behave like RAM.
+pub trait Device {
+    fn contains(&self, address: u16) -> bool;
+
+    fn load(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        memory[usize::from(address)]
+    }
+
+    fn store(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        memory[usize::from(address)] = value;
+    }
+
+    /// Called at the start of every cycle, before the CPU runs an instruction.
+    /// Memory is changed through `events`, so that decoded instructions
+    /// aren't kept past a change.
+    fn tick(&mut self, _events: &mut Events<'_>) {}
+
+    /// How many of the following cycles the device neither queues events nor
+    /// changes memory, so that the emulator may advance it with a single
+    /// `skip` instead of calling `tick` every cycle. With the default of
+    /// zero the device is ticked every cycle.
+    fn idle_cycles(&self) -> u64 {
+        0
+    }
+
+    /// Has the same effect as `cycles` calls to `tick`, where `cycles`
+    /// is at most `idle_cycles()`.
+    fn skip(&mut self, _memory: &[u8; MEMORY_SIZE], _cycles: u64) {}
+
+    /// Called when the emulator is reset, after memory was cleared.
+    fn reset(&mut self) {}
+}
+
+pub struct NoDevice;
+
+impl Device for NoDevice {
+    fn contains(&self, _address: u16) -> bool {
+        false
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        u64::MAX
+    }
+}
+
+/// Routes accesses to `A` first if both devices contain an address.
+impl<A: Device, B: Device> Device for (A, B) {
+    fn contains(&self, address: u16) -> bool {
+        self.0.contains(address) || self.1.contains(address)
+    }
+
+    fn load(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        if self.0.contains(address) {
+            self.0.load(memory, address)
+        } else {
+            self.1.load(memory, address)
+        }
+    }
+
+    fn store(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        if self.0.contains(address) {
+            self.0.store(memory, address, value)
+        } else {
+            self.1.store(memory, address, value)
+        }
+    }
+
+    fn tick(&mut self, events: &mut Events<'_>) {
+        self.0.tick(events);
+        self.1.tick(events);
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        self.0.idle_cycles().min(self.1.idle_cycles())
+    }
+
+    fn skip(&mut self, memory: &[u8; MEMORY_SIZE], cycles: u64) {
+        self.0.skip(memory, cycles);
+        self.1.skip(memory, cycles);
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+        self.1.reset();
+    }
+}
+
+impl<D: Device + ?Sized> Device for &mut D {
+    fn contains(&self, address: u16) -> bool {
+        (**self).contains(address)
+    }
+
+    fn load(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        (**self).load(memory, address)
+    }
+
+    fn store(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        (**self).store(memory, address, value)
+    }
+
+    fn tick(&mut self, events: &mut Events<'_>) {
+        (**self).tick(events)
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        (**self).idle_cycles()
+    }
+
+    fn skip(&mut self, memory: &[u8; MEMORY_SIZE], cycles: u64) {
+        (**self).skip(memory, cycles)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+pub(crate) const SCREEN_POSITION: u16 = 0b1100_0000_0000_0000;
+const SCREEN_END: u16 = SCREEN_POSITION + (SCREEN_WIDTH * SCREEN_HEIGHT) as u16 - 1;
+/// Shows video memory at `SCREEN_POSITION` on the screen and
+/// sends a screen refresh event every `refresh_time` cycles.
+pub(crate) struct Framebuffer {
+    pub(crate) screen: [[u8; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    pub(crate) time_to_refresh: u64,
+    pub(crate) refresh_time: u64,
+}
+
+impl Framebuffer {
+    pub(crate) const fn new(refresh_time: u64) -> Self {
+        Framebuffer {
+            screen: [[0; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            time_to_refresh: refresh_time,
+            refresh_time,
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn contains(&self, address: u16) -> bool {
+        (SCREEN_POSITION..=SCREEN_END).contains(&address)
+    }
+
+    fn tick(&mut self, events: &mut Events<'_>) {
+        if self.time_to_refresh == 0 {
+            let memory = events.memory();
+            for (row, data) in self.screen.iter_mut().enumerate() {
+                let start = usize::from(SCREEN_POSITION) + row * SCREEN_WIDTH;
+                data.copy_from_slice(&memory[start..(start + SCREEN_WIDTH)]);
+            }
+            self.time_to_refresh = self.refresh_time;
+            events.push(Event::screen_refresh());
+        }
+        self.time_to_refresh -= 1;
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        self.time_to_refresh
+    }
+
+    fn skip(&mut self, _memory: &[u8; MEMORY_SIZE], cycles: u64) {
+        self.time_to_refresh -= cycles;
+    }
+
+    fn reset(&mut self) {
+        *self = Framebuffer::new(self.refresh_time);
+    }
+}
@@ -0,0 +1,58 @@
+use super::decode::{self, MAX_INSTRUCTION_LEN};
+use super::{Device, Emulator, Instruction, Storage, DISK_SIZE, MEMORY_SIZE};
+
+pub(crate) struct InstructionCache {
+    // decoded instruction and its length by address
+    entries: Vec<Option<(Instruction, u8)>>,
+}
+
+impl InstructionCache {
+    pub(crate) fn invalidate(&mut self, addr: u16) {
+        // a store can change any instruction that starts up to
+        // the length of the longest instruction before it
+        for offset in 0..MAX_INSTRUCTION_LEN as u16 {
+            self.entries[usize::from(addr.wrapping_sub(offset))] = None;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for entry in &mut self.entries {
+            *entry = None;
+        }
+    }
+
+    pub(crate) fn fetch(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> (Instruction, usize) {
+        let entry = &mut self.entries[usize::from(address)];
+        if let Some((instruction, len)) = *entry {
+            return (instruction, usize::from(len));
+        }
+        let (instruction, len) = decode::decode_with(|offset| {
+            memory[usize::from(address.wrapping_add(offset as u16))]
+        });
+        *entry = Some((instruction, len as u8));
+        (instruction, len)
+    }
+}
+
+impl<SM, SD, T, D> Emulator<SM, SD, T, D>
+where
+    SM: Storage<[u8; MEMORY_SIZE]>,
+    SD: Storage<[u8; DISK_SIZE]>,
+    D: Device,
+{
+    /// Keeps every instruction decoded until the memory it was decoded from
+    /// changes, which speeds up running code. Memory changes made by devices
+    /// from `Device::tick` aren't noticed, so such devices must not write
+    /// over code.
+    pub fn enable_instruction_cache(&mut self) {
+        if self.instruction_cache.is_none() {
+            self.instruction_cache = Some(InstructionCache {
+                entries: vec![None; MEMORY_SIZE],
+            });
+        }
+    }
+
+    pub fn disable_instruction_cache(&mut self) {
+        self.instruction_cache = None;
+    }
+}
@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::ops::Range;
+use super::{palette, Tracer, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+struct State {
+    refreshes: u64,
+    frames: Vec<[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]>,
+}
+
+/// A `Tracer` that keeps a copy of the screen on every screen refresh
+/// whose index is in `frames`, the first refresh has index 0.
+pub struct ScreenCapture {
+    frames: Range<u64>,
+    // drop earlier frames when capturing a new one
+    keep_last: bool,
+    state: RefCell<State>,
+}
+
+impl ScreenCapture {
+    pub fn new(frames: Range<u64>) -> Self {
+        ScreenCapture {
+            frames,
+            keep_last: false,
+            state: RefCell::new(State {
+                refreshes: 0,
+                frames: Vec::new(),
+            }),
+        }
+    }
+
+    /// Like `new`, but only keeps the latest captured frame.
+    pub fn last_frame(frames: Range<u64>) -> Self {
+        ScreenCapture {
+            keep_last: true,
+            ..ScreenCapture::new(frames)
+        }
+    }
+
+    /// Screen refreshes seen so far, captured or not.
+    pub fn refreshes(&self) -> u64 {
+        self.state.borrow().refreshes
+    }
+
+    /// Returns the frames captured since the last call.
+    pub fn take_frames(&self) -> Vec<[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]> {
+        std::mem::take(&mut self.state.borrow_mut().frames)
+    }
+}
+
+impl Tracer for ScreenCapture {
+    fn on_screen_refresh(&self, screen: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]) {
+        let state = &mut *self.state.borrow_mut();
+        if self.frames.contains(&state.refreshes) {
+            if self.keep_last {
+                state.frames.clear();
+            }
+            state.frames.push(*screen);
+        }
+        state.refreshes += 1;
+    }
+}
+
+fn scaled_size(scale: u16) -> io::Result<(u16, u16)> {
+    let width = (SCREEN_WIDTH as u16).checked_mul(scale);
+    let height = (SCREEN_HEIGHT as u16).checked_mul(scale);
+    match (width, height) {
+        (Some(width), Some(height)) if scale > 0 => Ok((width, height)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid scale")),
+    }
+}
+
+// one palette index per pixel, every screen pixel becomes a scale x scale square
+fn upscale(screen: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], scale: u16) -> Vec<u8> {
+    let scale = usize::from(scale);
+    let mut pixels = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * scale * scale);
+    for row in screen {
+        for _ in 0..scale {
+            for &pixel in row {
+                pixels.extend(std::iter::repeat_n(pixel, scale));
+            }
+        }
+    }
+    pixels
+}
+
+fn rgb_palette() -> Vec<u8> {
+    (0..=255)
+        .flat_map(|index| {
+            let (r, g, b) = palette::color(index);
+            [r, g, b]
+        })
+        .collect()
+}
+
+/// Encodes a frame as a PNG image, with every pixel scaled up `scale` times.
+pub fn write_png(screen: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], scale: u16, out: impl Write) -> io::Result<()> {
+    let (width, height) = scaled_size(scale)?;
+    let mut encoder = png::Encoder::new(out, width.into(), height.into());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(rgb_palette());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&upscale(screen, scale))?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn gif_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidInput, error),
+    }
+}
+
+/// Encodes frames as a looping GIF animation, with every pixel scaled up
+/// `scale` times. `frame_delay` is in hundredths of a second.
+pub fn write_gif(
+    frames: &[[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]],
+    scale: u16,
+    frame_delay: u16,
+    out: impl Write,
+) -> io::Result<()> {
+    let (width, height) = scaled_size(scale)?;
+    let mut encoder = gif::Encoder::new(out, width, height, &rgb_palette()).map_err(gif_error)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+    for screen in frames {
+        encoder.write_frame(&gif::Frame {
+            width,
+            height,
+            delay: frame_delay,
+            buffer: Cow::Owned(upscale(screen, scale)),
+            ..gif::Frame::default()
+        }).map_err(gif_error)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use super::{OverflowPolicy, DEFAULT_EVENT_QUEUE_CAPACITY, DISK_SIZE, MAX_EVENT_QUEUE_CAPACITY, MEMORY_SIZE};
+
+/// Timing and capacity parameters of the machine, so that software can be
+/// tried on slower disks or different frame rates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MachineConfig {
+    /// Cycles per second when running in real time.
+    pub clock_rate: u64,
+    /// Cycles between screen refresh events.
+    pub screen_refresh_time: u64,
+    /// Cycles a disk takes to transfer one byte.
+    pub cycles_per_byte: u64,
+    /// Bytes transferred by a single disk read or write,
+    /// and loaded from disk 0 on reset.
+    pub disk_op_size: usize,
+    pub event_queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl MachineConfig {
+    /// The machine as it runs in the web frontend, 1.5 MHz at 20 frames per second.
+    pub const fn classic() -> Self {
+        MachineConfig {
+            clock_rate: 1024 * 1024 * 3 / 2,
+            screen_refresh_time: 78643,
+            cycles_per_byte: 32,
+            disk_op_size: 4096,
+            event_queue_capacity: DEFAULT_EVENT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+        }
+    }
+
+    /// Like `classic`, but disks transfer a byte every cycle.
+    pub const fn fast_disk() -> Self {
+        MachineConfig {
+            cycles_per_byte: 1,
+            ..MachineConfig::classic()
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.clock_rate > 0
+            && self.screen_refresh_time > 0
+            && self.cycles_per_byte > 0
+            && self.disk_op_size > 0
+            && self.disk_op_size <= MEMORY_SIZE
+            && self.disk_op_size <= DISK_SIZE
+            && self.event_queue_capacity > 0
+            && self.event_queue_capacity <= MAX_EVENT_QUEUE_CAPACITY
+    }
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        MachineConfig::classic()
+    }
+}
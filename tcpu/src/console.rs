@@ -0,0 +1,44 @@
+use super::{Device, MEMORY_SIZE};
+
+pub(crate) const CONSOLE_POSITION: u16 = 0xcc04;
+pub const CONSOLE_BUFFER_SIZE: usize = 4096;
+
+/// Serial console, bytes stored to `CONSOLE_POSITION` are collected
+/// until the host takes them.
+#[derive(Clone)]
+pub(crate) struct Console {
+    pub(crate) output: [u8; CONSOLE_BUFFER_SIZE],
+    pub(crate) len: usize,
+}
+
+impl Console {
+    pub(crate) const fn new() -> Self {
+        Console {
+            output: [0; CONSOLE_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn output(&self) -> &[u8] {
+        &self.output[..self.len]
+    }
+}
+
+impl Device for Console {
+    fn contains(&self, address: u16) -> bool {
+        address == CONSOLE_POSITION
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn store(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        memory[usize::from(address)] = value;
+        // output that doesn't fit is dropped until the host clears the buffer
+        if self.len < CONSOLE_BUFFER_SIZE {
+            self.output[self.len] = value;
+            self.len += 1;
+        }
+    }
+}
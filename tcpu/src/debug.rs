@@ -0,0 +1,202 @@
+use core::ops::RangeInclusive;
+use super::{CpuState, Device, Emulator, Instruction, Storage, Tracer, DISK_SIZE, MEMORY_SIZE};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum StopReason {
+    /// The CPU executed `halt`.
+    Halted,
+    /// The next instruction to execute is at a breakpoint.
+    Breakpoint(u16),
+    /// The last executed instruction accessed a watched address.
+    Watchpoint {
+        addr: u16,
+        value: u16,
+        wide: bool,
+    },
+    /// The CPU executed `wait`.
+    Waiting,
+    BudgetExhausted,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    fn read(self) -> bool {
+        self != Watch::Write
+    }
+
+    fn write(self) -> bool {
+        self != Watch::Read
+    }
+}
+
+#[derive(Clone)]
+struct AddressSet {
+    bits: [u64; MEMORY_SIZE / 64],
+}
+
+impl AddressSet {
+    const fn new() -> Self {
+        AddressSet {
+            bits: [0; MEMORY_SIZE / 64],
+        }
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        self.bits[usize::from(address / 64)] & (1 << (address % 64)) != 0
+    }
+
+    fn set(&mut self, address: u16, value: bool) {
+        let word = &mut self.bits[usize::from(address / 64)];
+        if value {
+            *word |= 1 << (address % 64);
+        } else {
+            *word &= !(1 << (address % 64));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Debugger {
+    breakpoints: AddressSet,
+    read_watchpoints: AddressSet,
+    write_watchpoints: AddressSet,
+    // fast path for data accesses when no watchpoints are set
+    watching: bool,
+    hit: Option<StopReason>,
+}
+
+impl Debugger {
+    pub(crate) const fn new() -> Self {
+        Debugger {
+            breakpoints: AddressSet::new(),
+            read_watchpoints: AddressSet::new(),
+            write_watchpoints: AddressSet::new(),
+            watching: false,
+            hit: None,
+        }
+    }
+
+    fn update_watching(&mut self) {
+        self.watching = !self.read_watchpoints.is_empty() || !self.write_watchpoints.is_empty();
+    }
+
+    fn check(&mut self, set: bool, addr: u16, value: u16, wide: bool) {
+        if set && self.hit.is_none() {
+            self.hit = Some(StopReason::Watchpoint { addr, value, wide });
+        }
+    }
+
+    #[inline]
+    pub(crate) fn on_load(&mut self, addr: u16, value: u16, wide: bool) {
+        if self.watching {
+            let watched = self.read_watchpoints.contains(addr)
+                || (wide && self.read_watchpoints.contains(addr.wrapping_add(1)));
+            self.check(watched, addr, value, wide);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn on_store(&mut self, addr: u16, value: u16, wide: bool) {
+        if self.watching {
+            let watched = self.write_watchpoints.contains(addr)
+                || (wide && self.write_watchpoints.contains(addr.wrapping_add(1)));
+            self.check(watched, addr, value, wide);
+        }
+    }
+}
+
+impl<SM, SD, T, D> Emulator<SM, SD, T, D>
+where
+    SM: Storage<[u8; MEMORY_SIZE]>,
+    SD: Storage<[u8; DISK_SIZE]>,
+    T: Tracer,
+    D: Device,
+{
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.debugger.breakpoints.set(address, true);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.debugger.breakpoints.set(address, false);
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, watch: Watch) {
+        for address in range {
+            if watch.read() {
+                self.debugger.read_watchpoints.set(address, true);
+            }
+            if watch.write() {
+                self.debugger.write_watchpoints.set(address, true);
+            }
+        }
+        self.debugger.update_watching();
+    }
+
+    pub fn remove_watchpoint(&mut self, range: RangeInclusive<u16>, watch: Watch) {
+        for address in range {
+            if watch.read() {
+                self.debugger.read_watchpoints.set(address, false);
+            }
+            if watch.write() {
+                self.debugger.write_watchpoints.set(address, false);
+            }
+        }
+        self.debugger.update_watching();
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints = AddressSet::new();
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.debugger.read_watchpoints = AddressSet::new();
+        self.debugger.write_watchpoints = AddressSet::new();
+        self.debugger.watching = false;
+    }
+
+    /// Runs for at most `cycles` cycles, stopping early on a breakpoint,
+    /// a watchpoint, `wait` or `halt`. A breakpoint on the instruction the
+    /// CPU is about to execute when this is called is ignored, so that
+    /// execution can be resumed after stopping on it.
+    pub fn run_until(&mut self, cycles: u64) -> StopReason {
+        if self.state == CpuState::Halted {
+            return StopReason::Halted;
+        }
+        let mut skip_breakpoint = self.state == CpuState::Running;
+        self.debugger.hit = None;
+        for _ in 0..cycles {
+            if !self.instruction_pending {
+                self.start_cycle();
+            }
+            if self.state == CpuState::Running
+                && !skip_breakpoint
+                && self.debugger.breakpoints.contains(self.instruction_pointer)
+            {
+                self.instruction_pending = true;
+                return StopReason::Breakpoint(self.instruction_pointer);
+            }
+            skip_breakpoint = false;
+            self.instruction_pending = false;
+            let executed = self.execute();
+            if let Some(hit) = self.debugger.hit.take() {
+                return hit;
+            }
+            match (executed, &self.state) {
+                (_, CpuState::Halted) => return StopReason::Halted,
+                (Some(Instruction::Wait), CpuState::Waiting) => return StopReason::Waiting,
+                _ => {}
+            }
+        }
+        StopReason::BudgetExhausted
+    }
+}
@@ -0,0 +1,221 @@
+use core::fmt;
+use super::{Address, Instruction, Operand, Register, DISK_IDS};
+
+struct Decoder<F> {
+    fetch: F,
+    len: usize,
+}
+
+impl<F: FnMut(usize) -> u8> Decoder<F> {
+    fn read_byte(&mut self) -> u8 {
+        let byte = (self.fetch)(self.len);
+        self.len += 1;
+        byte
+    }
+
+    fn read_word(&mut self) -> u16 {
+        let low = u16::from(self.read_byte());
+        let high = u16::from(self.read_byte());
+        (high << 8) + low
+    }
+
+    fn decode_instruction(&mut self) -> Instruction {
+        let x = self.read_byte();
+        match x {
+            0b0000_0000 => Instruction::Nop,
+            0b0000_0001 => Instruction::Ret,
+            0b0000_0010 => Instruction::Wait,
+            0b0000_0011 => Instruction::Poll,
+            0b0000_0100 => Instruction::Halt,
+            0b1000_0000 => self.register_operand(Instruction::Mov),
+            0b1000_0001 => self.register_operand(Instruction::Add),
+            0b1000_0010 => self.register_operand(Instruction::Sub),
+            0b1000_0011 => self.register_operand(Instruction::Xor),
+            0b1000_0100 => self.register_operand(Instruction::And),
+            0b1000_0101 => self.register_operand(Instruction::Or),
+            0b1000_0110 => self.register_operand(Instruction::Shl),
+            0b1000_0111 => self.register_operand(Instruction::Shr),
+            0b1000_1000 => self.register_operand(Instruction::Cmp),
+            0b1001_0000 ..= 0b1001_0010 => self.decode_load(x, Instruction::Load),
+            0b1001_0100 ..= 0b1001_0110 => self.decode_load(x, Instruction::Loadw),
+            0b1001_1000 ..= 0b1001_1010 => self.decode_store(x, Instruction::Store),
+            0b1001_1100 ..= 0b1001_1110 => self.decode_store(x, Instruction::Storew),
+            0b0001_0000 ..= 0b0001_0111 => Instruction::Not(decode_register(x)),
+            0b0010_0000 ..= 0b0010_0111 => Instruction::Neg(decode_register(x)),
+            0b0011_0000 ..= 0b0011_0111 => Instruction::Pop(decode_register(x)),
+            0b0100_0000 ..= 0b0100_1111 => Instruction::Push(self.decode_operand(x)),
+            0b0101_0000 ..= 0b0101_1111 => Instruction::Jmp(self.decode_operand(x)),
+            0b0110_0000 ..= 0b0110_1111 => Instruction::Call(self.decode_operand(x)),
+            0b1010_0000 => self.register_operand(Instruction::Jez),
+            0b1010_0001 => self.register_operand(Instruction::Jnz),
+            0b1010_0010 => self.register_operand(Instruction::Jl),
+            0b1010_0011 => self.register_operand(Instruction::Jg),
+            0b1010_0100 => self.register_operand(Instruction::Jle),
+            0b1010_0101 => self.register_operand(Instruction::Jge),
+            0b1111_0000 ..= 0b1111_0111 => {
+                let disk = DISK_IDS[usize::from(x & 0b111)];
+                self.two_operands(|a, b| Instruction::Read(disk, a, b))
+            }
+            0b1111_1000 ..= 0b1111_1111 => {
+                let disk = DISK_IDS[usize::from(x & 0b111)];
+                self.two_operands(|a, b| Instruction::Write(disk, a, b))
+            }
+            _ => Instruction::Invalid,
+        }
+    }
+
+    fn register_operand(&mut self, f: impl FnOnce(Register, Operand) -> Instruction) -> Instruction {
+        let x = self.read_byte();
+        let reg = decode_register(x >> 4);
+        let op = self.decode_operand(x);
+        f(reg, op)
+    }
+
+    fn two_operands(&mut self, f: impl FnOnce(Operand, Operand) -> Instruction) -> Instruction {
+        let x = self.read_byte();
+        let op1 = self.decode_operand(x >> 4);
+        let op2 = self.decode_operand(x);
+        f(op1, op2)
+    }
+
+    fn decode_load(&mut self, b: u8, f: impl FnOnce(Register, Address) -> Instruction) -> Instruction {
+        let x = self.read_byte();
+        let offset = self.decode_offset(b);
+        let reg = decode_register(x >> 4);
+        let operand = self.decode_operand(x);
+        f(reg, Address { operand, offset })
+    }
+
+    fn decode_store(&mut self, b: u8, f: impl FnOnce(Operand, Address) -> Instruction) -> Instruction {
+        let x = self.read_byte();
+        let offset = self.decode_offset(b);
+        let op = self.decode_operand(x >> 4);
+        let operand = self.decode_operand(x);
+        f(op, Address { operand, offset })
+    }
+
+    fn decode_offset(&mut self, b: u8) -> u16 {
+        match b & 0b11 {
+            1 => u16::from(self.read_byte()),
+            2 => self.read_word(),
+            _ => 0,
+        }
+    }
+
+    fn decode_operand(&mut self, bits: u8) -> Operand {
+        match bits & 0b1111 {
+            0b000 => Operand::Register(Register::A),
+            0b001 => Operand::Register(Register::B),
+            0b010 => Operand::Register(Register::C),
+            0b011 => Operand::Register(Register::D),
+            0b100 => Operand::Register(Register::I),
+            0b101 => Operand::Register(Register::J),
+            0b110 => Operand::Register(Register::P),
+            0b111 => Operand::Register(Register::S),
+            0b1000 => Operand::Word(0),
+            0b1001 => Operand::Word(1),
+            0b1010 => Operand::Word(2),
+            0b1011 => Operand::Word(3),
+            0b1100 => Operand::Word(4),
+            0b1101 => Operand::Word(u16::from(self.read_byte())),
+            0b1110 => Operand::Word(self.read_word()),
+            0b1111 => Operand::Word(0xffff),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn decode_register(bits: u8) -> Register {
+    match bits & 0b111 {
+        0b000 => Register::A,
+        0b001 => Register::B,
+        0b010 => Register::C,
+        0b011 => Register::D,
+        0b100 => Register::I,
+        0b101 => Register::J,
+        0b110 => Register::P,
+        0b111 => Register::S,
+        _ => unreachable!(),
+    }
+}
+
+/// Length of the longest instruction, a `storew` with a word offset,
+/// a word value and a word address.
+pub(crate) const MAX_INSTRUCTION_LEN: usize = 8;
+
+/// Decodes a single instruction, `fetch` is given offsets of instruction
+/// bytes relative to its start. Returns the instruction and its length.
+pub(crate) fn decode_with(fetch: impl FnMut(usize) -> u8) -> (Instruction, usize) {
+    let mut decoder = Decoder { fetch, len: 0 };
+    let instruction = decoder.decode_instruction();
+    (instruction, decoder.len)
+}
+
+/// Decodes the instruction at `bytes[addr]` the same way the CPU would if
+/// `bytes` was loaded at address 0: reads wrap around at the end of the
+/// address space, and bytes past the end of `bytes` read as zeros.
+pub fn decode(bytes: &[u8], addr: u16) -> (Instruction, usize) {
+    decode_with(|offset| {
+        let address = addr.wrapping_add(offset as u16);
+        bytes.get(usize::from(address)).copied().unwrap_or(0)
+    })
+}
+
+/// Decodes consecutive instructions from `bytes`, assuming that the first
+/// byte is located at address `origin`. Addresses don't wrap, so that
+/// listings of disk images show offsets into the whole disk.
+pub fn disassemble(bytes: &[u8], origin: usize) -> Disassembly<'_> {
+    Disassembly {
+        bytes,
+        origin,
+        position: 0,
+    }
+}
+
+pub struct Disassembly<'a> {
+    bytes: &'a [u8],
+    origin: usize,
+    position: usize,
+}
+
+impl<'a> Iterator for Disassembly<'a> {
+    type Item = DisassembledInstruction<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.position;
+        if start >= self.bytes.len() {
+            return None;
+        }
+        let bytes = self.bytes;
+        let (instruction, len) = decode_with(|offset| bytes.get(start + offset).copied().unwrap_or(0));
+        let end = core::cmp::min(start + len, bytes.len());
+        self.position = end;
+        Some(DisassembledInstruction {
+            address: self.origin + start,
+            bytes: &bytes[start..end],
+            instruction,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DisassembledInstruction<'a> {
+    pub address: usize,
+    /// Instruction bytes, shorter than the instruction if it was
+    /// cut off by the end of the disassembled range.
+    pub bytes: &'a [u8],
+    pub instruction: Instruction,
+}
+
+impl fmt::Display for DisassembledInstruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:", self.address)?;
+        for byte in self.bytes {
+            write!(f, " {:02x}", byte)?;
+        }
+        for _ in self.bytes.len()..MAX_INSTRUCTION_LEN {
+            write!(f, "   ")?;
+        }
+        write!(f, "  {}", self.instruction)
+    }
+}
@@ -0,0 +1,190 @@
+use core::convert::TryFrom;
+use core::fmt;
+use super::snapshot::{Reader, Writer};
+use super::{DiskMemory, SnapshotError, Storage, DISK_SIZE};
+
+// layout, all numbers little endian:
+//   magic, version u16, flags u8, label length u16, label utf-8,
+//   crc-32 of the disk contents u32, encoding u8, payload
+// raw payload is the whole disk, sparse payload is a u16 count of blocks
+// followed by the u16 index and the contents of every block that isn't all zeros
+const MAGIC: &[u8; 8] = b"TCPUDISK";
+const VERSION: u16 = 1;
+
+const FLAG_READ_ONLY: u8 = 1;
+
+const BLOCK_SIZE: usize = 256;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DiskImageError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid,
+    ChecksumMismatch,
+    /// A raw image is larger than `DISK_SIZE`.
+    TooLarge,
+}
+
+impl fmt::Display for DiskImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DiskImageError::BadMagic => write!(f, "not a tcpu disk image"),
+            DiskImageError::UnsupportedVersion(v) => write!(f, "unsupported disk image version {}", v),
+            DiskImageError::Truncated => write!(f, "disk image is truncated"),
+            DiskImageError::Invalid => write!(f, "disk image is corrupted"),
+            DiskImageError::ChecksumMismatch => write!(f, "disk image checksum doesn't match its contents"),
+            DiskImageError::TooLarge => write!(f, "disk image is larger than {} bytes", DISK_SIZE),
+        }
+    }
+}
+
+impl std::error::Error for DiskImageError {}
+
+impl From<SnapshotError> for DiskImageError {
+    fn from(error: SnapshotError) -> Self {
+        match error {
+            SnapshotError::Truncated => DiskImageError::Truncated,
+            _ => DiskImageError::Invalid,
+        }
+    }
+}
+
+/// How `DiskImage::encode` stores the disk contents.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DiskEncoding {
+    Raw,
+    /// Leaves out blocks of zeros, much smaller for mostly empty disks.
+    Sparse,
+}
+
+/// Disk contents together with the metadata the web frontend keeps for them.
+pub struct DiskImage {
+    pub label: String,
+    pub read_only: bool,
+    data: DiskMemory,
+}
+
+impl DiskImage {
+    pub fn new(label: &str) -> Self {
+        DiskImage {
+            label: label.to_owned(),
+            read_only: false,
+            data: DiskMemory::default(),
+        }
+    }
+
+    /// Makes an unlabeled image from raw disk contents, shorter
+    /// contents are padded with zeros.
+    pub fn from_raw(raw: &[u8]) -> Result<Self, DiskImageError> {
+        if raw.len() > DISK_SIZE {
+            return Err(DiskImageError::TooLarge);
+        }
+        let mut image = DiskImage::new("");
+        image.data.as_mut()[..raw.len()].copy_from_slice(raw);
+        Ok(image)
+    }
+
+    /// Decodes `bytes` as a disk image if they start with its magic,
+    /// otherwise treats them as raw disk contents.
+    pub fn load(bytes: &[u8]) -> Result<Self, DiskImageError> {
+        if bytes.starts_with(MAGIC) {
+            Self::decode(bytes)
+        } else {
+            Self::from_raw(bytes)
+        }
+    }
+
+    pub fn data(&self) -> &[u8; DISK_SIZE] {
+        self.data.as_ref()
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8; DISK_SIZE] {
+        self.data.as_mut()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the label is longer than 65535 bytes.
+    pub fn encode(&self, encoding: DiskEncoding) -> Vec<u8> {
+        let label = u16::try_from(self.label.len()).expect("disk label is too long");
+        let mut w = Writer { out: Vec::new() };
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u8(if self.read_only { FLAG_READ_ONLY } else { 0 });
+        w.u16(label);
+        w.bytes(self.label.as_bytes());
+        w.u32(crc32(self.data()));
+        match encoding {
+            DiskEncoding::Raw => {
+                w.u8(0);
+                w.bytes(self.data());
+            }
+            DiskEncoding::Sparse => {
+                w.u8(1);
+                let blocks = self.data().chunks(BLOCK_SIZE)
+                    .enumerate()
+                    .filter(|(_, block)| block.iter().any(|&byte| byte != 0))
+                    .collect::<Vec<_>>();
+                w.u16(blocks.len() as u16);
+                for (index, block) in blocks {
+                    w.u16(index as u16);
+                    w.bytes(block);
+                }
+            }
+        }
+        w.out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DiskImageError> {
+        let mut r = Reader { input: bytes };
+        if r.bytes(MAGIC.len()).map_err(|_| DiskImageError::BadMagic)? != MAGIC {
+            return Err(DiskImageError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(DiskImageError::UnsupportedVersion(version));
+        }
+        let flags = r.u8()?;
+        if flags & !FLAG_READ_ONLY != 0 {
+            return Err(DiskImageError::Invalid);
+        }
+        let label_len = usize::from(r.u16()?);
+        let label = std::str::from_utf8(r.bytes(label_len)?).map_err(|_| DiskImageError::Invalid)?;
+        let mut image = DiskImage::new(label);
+        image.read_only = flags & FLAG_READ_ONLY != 0;
+        let checksum = r.u32()?;
+        match r.u8()? {
+            0 => image.data_mut().copy_from_slice(r.bytes(DISK_SIZE)?),
+            1 => {
+                let count = r.u16()?;
+                for _ in 0..count {
+                    let start = usize::from(r.u16()?) * BLOCK_SIZE;
+                    if start >= DISK_SIZE {
+                        return Err(DiskImageError::Invalid);
+                    }
+                    image.data_mut()[start..][..BLOCK_SIZE].copy_from_slice(r.bytes(BLOCK_SIZE)?);
+                }
+            }
+            _ => return Err(DiskImageError::Invalid),
+        }
+        if !r.input.is_empty() {
+            return Err(DiskImageError::Invalid);
+        }
+        if crc32(image.data()) != checksum {
+            return Err(DiskImageError::ChecksumMismatch);
+        }
+        Ok(image)
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
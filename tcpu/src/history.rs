@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use super::sound::SoundState;
+use super::{
+    CpuState, Device, Emulator, Event, Registers, SlotDisk, Storage, Timer,
+    DISK_SIZE, MAX_DISKS, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+// state of the machine at the start of a cycle, except for memory,
+// disk contents, queued events and the screen, which are undone
+// byte by byte from the changes recorded during the cycle
+struct Frame {
+    registers: Registers,
+    instruction_pointer: u16,
+    state: CpuState,
+    cycles: u64,
+    time_to_refresh: u64,
+    timer: Timer,
+    console_len: usize,
+    sound: SoundState,
+    queue_head: usize,
+    queue_len: usize,
+    queue_lost: u64,
+    queue_unreported: u16,
+    // state of the drives, `Missing` for drives that aren't connected
+    disks: [SlotDisk; MAX_DISKS],
+    // number of entries in `History::changes` made during this cycle
+    changes: usize,
+}
+
+enum Change {
+    Memory(u16, u8),
+    Disk(u8, u32, u8),
+    Event(u8, Event),
+    Screen(Box<[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]>),
+}
+
+pub(crate) struct History {
+    capacity: usize,
+    frames: VecDeque<Frame>,
+    changes: VecDeque<Change>,
+}
+
+impl History {
+    fn push(&mut self, change: Change) {
+        // changes made before the first recorded cycle can't be undone anyway
+        if let Some(frame) = self.frames.back_mut() {
+            frame.changes += 1;
+            self.changes.push_back(change);
+        }
+    }
+
+    pub(crate) fn memory(&mut self, addr: u16, old: u8) {
+        self.push(Change::Memory(addr, old));
+    }
+
+    pub(crate) fn disk(&mut self, slot: usize, addr: usize, old: u8) {
+        self.push(Change::Disk(slot as u8, addr as u32, old));
+    }
+
+    pub(crate) fn event(&mut self, index: usize, old: Event) {
+        self.push(Change::Event(index as u8, old));
+    }
+
+    pub(crate) fn screen(&mut self, old: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]) {
+        self.push(Change::Screen(Box::new(*old)));
+    }
+
+    fn push_frame(&mut self, frame: Frame) {
+        if self.frames.len() == self.capacity {
+            if let Some(oldest) = self.frames.pop_front() {
+                self.changes.drain(..oldest.changes);
+            }
+        }
+        self.frames.push_back(frame);
+    }
+}
+
+impl<SM, SD, T, D> Emulator<SM, SD, T, D>
+where
+    SM: Storage<[u8; MEMORY_SIZE]>,
+    SD: Storage<[u8; DISK_SIZE]>,
+    D: Device,
+{
+    /// Starts recording the changes made by each cycle, so that up to
+    /// `cycles` most recent cycles can be undone with `step_back`.
+    /// Changes made through `memory_mut`, `disk_slot` and `disk` are not
+    /// recorded, and `reset` or `restore` discard the recorded history.
+    /// As with `snapshot`, the state of devices added with `with_devices`
+    /// isn't recorded, `step_back` leaves them as they are. Memory they
+    /// change through `Events` is restored.
+    pub fn enable_history(&mut self, cycles: usize) {
+        self.history = Some(History {
+            capacity: cycles,
+            frames: VecDeque::new(),
+            changes: VecDeque::new(),
+        });
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Number of cycles that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.frames.len())
+    }
+
+    pub(crate) fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.frames.clear();
+            history.changes.clear();
+        }
+    }
+
+    pub(crate) fn save_frame(&mut self) {
+        if let Some(history) = &mut self.history {
+            if history.capacity == 0 {
+                return;
+            }
+            let mut disks = [SlotDisk::Missing; MAX_DISKS];
+            for (disk, slot) in disks.iter_mut().zip(&self.disk_slots) {
+                if let Some(slot) = slot {
+                    *disk = slot.disk;
+                }
+            }
+            let ((timer, (console, sound)), _) = &self.bus;
+            history.push_frame(Frame {
+                registers: self.registers,
+                instruction_pointer: self.instruction_pointer,
+                state: self.state,
+                cycles: self.cycles,
+                time_to_refresh: self.framebuffer.time_to_refresh,
+                timer: *timer,
+                console_len: console.len,
+                sound: sound.state,
+                queue_head: self.event_queue.head,
+                queue_len: self.event_queue.len,
+                queue_lost: self.event_queue.lost,
+                queue_unreported: self.event_queue.unreported,
+                disks,
+                changes: 0,
+            });
+        }
+    }
+
+    fn undo_cycle(&mut self) -> bool {
+        let history = match &mut self.history {
+            Some(history) => history,
+            None => return false,
+        };
+        let frame = match history.frames.pop_back() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        for _ in 0..frame.changes {
+            match history.changes.pop_back() {
+                Some(Change::Memory(addr, old)) => {
+                    self.memory.as_mut()[usize::from(addr)] = old;
+                    if let Some(cache) = &mut self.instruction_cache {
+                        cache.invalidate(addr);
+                    }
+                }
+                Some(Change::Disk(slot, addr, old)) => {
+                    if let Some(slot) = &mut self.disk_slots[usize::from(slot)] {
+                        slot.data.write(addr as usize, &[old]);
+                    }
+                }
+                Some(Change::Event(index, old)) => self.event_queue.items[usize::from(index)] = old,
+                Some(Change::Screen(old)) => self.framebuffer.screen = *old,
+                None => unreachable!(),
+            }
+        }
+        for (slot, disk) in self.disk_slots.iter_mut().flatten().zip(frame.disks.iter()) {
+            slot.disk = *disk;
+        }
+        self.registers = frame.registers;
+        self.instruction_pointer = frame.instruction_pointer;
+        self.state = frame.state;
+        self.instruction_pending = false;
+        self.cycles = frame.cycles;
+        self.framebuffer.time_to_refresh = frame.time_to_refresh;
+        let ((timer, (console, sound)), _) = &mut self.bus;
+        *timer = frame.timer;
+        console.len = frame.console_len;
+        sound.state = frame.sound;
+        self.event_queue.head = frame.queue_head;
+        self.event_queue.len = frame.queue_len;
+        self.event_queue.lost = frame.queue_lost;
+        self.event_queue.unreported = frame.queue_unreported;
+        true
+    }
+
+    /// Undoes up to `cycles` most recent cycles, returns how many
+    /// were undone. Inputs made since then are undone as well.
+    pub fn step_back(&mut self, cycles: usize) -> usize {
+        let mut undone = 0;
+        while undone < cycles && self.undo_cycle() {
+            undone += 1;
+        }
+        undone
+    }
+
+    /// Steps back until the CPU is about to execute the instruction at
+    /// `address`. Returns `false` if the recorded history ran out first,
+    /// leaving the emulator at the oldest recorded cycle.
+    pub fn run_back_to(&mut self, address: u16) -> bool {
+        loop {
+            let (state, instruction_pointer) = (self.state, self.instruction_pointer);
+            if !self.undo_cycle() {
+                return false;
+            }
+            // a waiting CPU only executes an instruction in cycles it wakes up in
+            let executed = match self.state {
+                CpuState::Running => true,
+                CpuState::Waiting => state != CpuState::Waiting || instruction_pointer != self.instruction_pointer,
+                CpuState::Halted => false,
+            };
+            if executed && self.instruction_pointer == address {
+                return true;
+            }
+        }
+    }
+}
@@ -48,23 +48,113 @@ mod box_storage {
 #[cfg(any(test, feature = "std"))]
 pub use box_storage::{Memory, DiskMemory};
 
+mod debug;
+pub use debug::{StopReason, Watch};
+
+#[cfg(any(test, feature = "std"))]
+mod profile;
+#[cfg(any(test, feature = "std"))]
+pub use profile::{AddressProfile, FunctionProfile, Profiler};
+
+#[cfg(any(test, feature = "std"))]
+mod history;
+#[cfg(any(test, feature = "std"))]
+use history::History;
+
+// recording history needs an allocator, so without `std` it's never enabled
+#[cfg(not(any(test, feature = "std")))]
+enum History {}
+
+#[cfg(not(any(test, feature = "std")))]
+impl History {
+    fn memory(&mut self, _addr: u16, _old: u8) { match *self {} }
+    fn disk(&mut self, _slot: usize, _addr: usize, _old: u8) { match *self {} }
+    fn event(&mut self, _index: usize, _old: Event) { match *self {} }
+    fn screen(&mut self, _old: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]) { match *self {} }
+}
+
+#[cfg(any(test, feature = "std"))]
+mod cache;
+#[cfg(any(test, feature = "std"))]
+use cache::InstructionCache;
+
+// same as history, the cache is never enabled without `std`
+#[cfg(not(any(test, feature = "std")))]
+enum InstructionCache {}
+
+#[cfg(not(any(test, feature = "std")))]
+impl InstructionCache {
+    fn invalidate(&mut self, _addr: u16) { match *self {} }
+    fn clear(&mut self) { match *self {} }
+    fn fetch(&mut self, _memory: &[u8; MEMORY_SIZE], _address: u16) -> (Instruction, usize) { match *self {} }
+}
+
+mod bus;
+pub use bus::{Device, NoDevice};
+use bus::Framebuffer;
+
+mod config;
+pub use config::MachineConfig;
+
+mod timer;
+use timer::Timer;
+
+mod console;
+pub use console::CONSOLE_BUFFER_SIZE;
+use console::{Console, CONSOLE_POSITION};
+
+mod sound;
+pub use sound::{AUDIO_BUFFER_SIZE, CYCLES_PER_SAMPLE};
+use sound::Sound;
+
+// the built-in devices come before the ones added with `with_devices`
+type Bus<D> = ((Timer, (Console, Sound)), D);
+
+pub mod palette;
+
+#[cfg(feature = "capture")]
+mod capture;
+#[cfg(feature = "capture")]
+pub use capture::{write_gif, write_png, ScreenCapture};
+
+mod decode;
+pub use decode::{decode, disassemble, DisassembledInstruction, Disassembly};
+
+#[cfg(any(test, feature = "std"))]
+mod snapshot;
+#[cfg(any(test, feature = "std"))]
+pub use snapshot::SnapshotError;
+
+#[cfg(any(test, feature = "std"))]
+mod replay;
+#[cfg(any(test, feature = "std"))]
+pub use replay::{Input, InputLog, Recorder, ReplayError};
+
+#[cfg(any(test, feature = "std"))]
+mod disk_image;
+#[cfg(any(test, feature = "std"))]
+pub use disk_image::{DiskEncoding, DiskImage, DiskImageError};
+
+#[cfg(any(test, feature = "std"))]
+mod overlay;
+#[cfg(any(test, feature = "std"))]
+pub use overlay::OverlayDisk;
+
 use core::fmt;
 
 pub const DISK_SIZE: usize = 1 << 20;
+/// Disk operations address disks in blocks of this many bytes.
+pub const DISK_BLOCK_SIZE: usize = 16;
 pub const MEMORY_SIZE: usize = 1 << 16;
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 48;
 
-const SCREEN_POSITION: u16 = 0b1100_0000_0000_0000;
-const SCREEN_REFRESH_TIME: u64 = 78643;
-const DISK_OP_SIZE: usize = 4096;
-const CYCLES_PER_BYTE: u64 = 32;
-
+/// Event delivered by `wait` or `poll`, with `id` in register A and `arg` in B.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
-struct Event {
-    id: u16,
-    arg: u16,
+pub struct Event {
+    pub id: u16,
+    pub arg: u16,
 }
 
 impl Event {
@@ -80,61 +170,203 @@ impl Event {
         Event { id: 3, arg: 0 }
     }
 
+    fn timer() -> Self {
+        Event { id: 6, arg: 0 }
+    }
+
+    fn console_input(byte: u8) -> Self {
+        Event { id: 7, arg: u16::from(byte) }
+    }
+
+    fn events_lost(count: u16) -> Self {
+        Event { id: 8, arg: count }
+    }
+
     fn disk_finished(disk: DiskId, result: DiskResult) -> Self {
+        // drives added after the first two got the ids after events lost
         let id = match disk {
             DiskId::D0 => 4,
             DiskId::D1 => 5,
+            disk => 7 + disk.index() as u16,
         };
         let arg = match result {
             DiskResult::Ok => 0,
             DiskResult::DiskNotPresent => 1,
             DiskResult::DiskBusy => 2,
+            DiskResult::DiskNotWriteable => 3,
         };
         Event { id, arg }
     }
 }
 
-const EVENT_QUEUE_CAPACITY: usize = 64;
+pub const DEFAULT_EVENT_QUEUE_CAPACITY: usize = 64;
+pub const MAX_EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// What happens to events that arrive while the event queue is full.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum OverflowPolicy {
+    DropOldest,
+    DropNewest,
+    /// Like `DropOldest`, but a screen refresh is also dropped whenever
+    /// another one is still waiting in the queue, even if it isn't full.
+    CoalesceScreenRefresh,
+}
 
 #[derive(Clone)]
 struct EventQueue {
-    items: [Event; EVENT_QUEUE_CAPACITY],
+    items: [Event; MAX_EVENT_QUEUE_CAPACITY],
     head: usize,
     len: usize,
+    capacity: usize,
+    policy: OverflowPolicy,
+    // total number of events dropped, for the host
+    lost: u64,
+    // events dropped since the guest was last told about it
+    unreported: u16,
 }
 
 impl EventQueue {
     const fn new() -> Self {
         EventQueue {
-            items: [Event { id: 0, arg: 0 }; EVENT_QUEUE_CAPACITY],
+            items: [Event { id: 0, arg: 0 }; MAX_EVENT_QUEUE_CAPACITY],
             head: 0,
             len: 0,
+            capacity: DEFAULT_EVENT_QUEUE_CAPACITY,
+            policy: OverflowPolicy::DropOldest,
+            lost: 0,
+            unreported: 0,
         }
     }
 
-    fn push(&mut self, event: Event) {
-        self.items[(self.head + self.len) % EVENT_QUEUE_CAPACITY] = event;
-        if self.len == EVENT_QUEUE_CAPACITY {
-            self.head = (self.head + 1) % EVENT_QUEUE_CAPACITY;
+    fn clear(&mut self) {
+        *self = EventQueue {
+            capacity: self.capacity,
+            policy: self.policy,
+            ..EventQueue::new()
+        };
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Event> + '_ {
+        (0..self.len).map(move |i| self.items[(self.head + i) % self.capacity])
+    }
+
+    fn coalesces(&self, event: Event) -> bool {
+        self.policy == OverflowPolicy::CoalesceScreenRefresh
+            && event == Event::screen_refresh()
+            && self.iter().any(|queued| queued == event)
+    }
+
+    // index of the slot the next pushed event is written to,
+    // or `None` if it is dropped
+    fn push_index(&self, event: Event) -> Option<usize> {
+        let full = self.len == self.capacity;
+        if self.coalesces(event) || (full && self.policy == OverflowPolicy::DropNewest) {
+            None
         } else {
-            self.len += 1;
+            Some((self.head + self.len) % self.capacity)
         }
     }
 
+    fn push(&mut self, event: Event) {
+        if self.coalesces(event) {
+            return;
+        }
+        if self.len == self.capacity {
+            self.lose_event();
+            if self.policy == OverflowPolicy::DropNewest {
+                return;
+            }
+            self.head = (self.head + 1) % self.capacity;
+            self.len -= 1;
+        }
+        self.items[(self.head + self.len) % self.capacity] = event;
+        self.len += 1;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0 && self.unreported == 0
+    }
+
+    fn lose_event(&mut self) {
+        self.lost += 1;
+        self.unreported = self.unreported.saturating_add(1);
+    }
+
     fn pop(&mut self) -> Option<Event> {
-        if self.len == 0 {
+        if self.unreported != 0 {
+            let event = Event::events_lost(self.unreported);
+            self.unreported = 0;
+            Some(event)
+        } else if self.len == 0 {
             None
         } else {
             // use index modulo capacity so that
             // bounds check would be optimized out
-            let event = self.items[self.head % EVENT_QUEUE_CAPACITY];
-            self.head = (self.head + 1) % EVENT_QUEUE_CAPACITY;
+            let event = self.items[self.head % MAX_EVENT_QUEUE_CAPACITY];
+            self.head = (self.head + 1) % self.capacity;
             self.len -= 1;
             Some(event)
         }
     }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        assert!(
+            capacity > 0 && capacity <= MAX_EVENT_QUEUE_CAPACITY,
+            "event queue capacity must be between 1 and {}",
+            MAX_EVENT_QUEUE_CAPACITY,
+        );
+        let old = self.clone();
+        // keep the newest events that fit
+        let skip = old.len.saturating_sub(capacity);
+        self.capacity = capacity;
+        self.head = 0;
+        self.len = 0;
+        for event in old.iter().skip(skip) {
+            self.items[self.len] = event;
+            self.len += 1;
+        }
+        for _ in 0..skip {
+            self.lose_event();
+        }
+    }
+}
+
+/// Lets devices queue events for the CPU and access memory.
+pub struct Events<'a> {
+    memory: &'a mut [u8; MEMORY_SIZE],
+    queue: &'a mut EventQueue,
+    history: &'a mut Option<History>,
+    instruction_cache: &'a mut Option<InstructionCache>,
 }
 
+impl Events<'_> {
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        self.memory
+    }
+
+    /// Changes a byte of memory. Unlike stores the CPU makes, it isn't
+    /// routed to the device that contains the address.
+    pub fn store(&mut self, address: u16, value: u8) {
+        if let Some(history) = self.history {
+            history.memory(address, self.memory[usize::from(address)]);
+        }
+        if let Some(cache) = self.instruction_cache {
+            cache.invalidate(address);
+        }
+        self.memory[usize::from(address)] = value;
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if let Some(history) = self.history {
+            if let Some(index) = self.queue.push_index(event) {
+                history.event(index, self.queue.items[index]);
+            }
+        }
+        self.queue.push(event);
+    }
+}
+
+#[derive(Copy, Clone)]
 enum DiskOp {
     Reading {
         disk_ptr: usize,
@@ -150,18 +382,52 @@ enum DiskOp {
     },
 }
 
+/// Most disk drives a machine can have, limited by the 3 bits
+/// `read` and `write` use to encode the drive.
+pub const MAX_DISKS: usize = 8;
+
 #[derive(PartialEq, Eq, Debug, Hash, Copy, Clone)]
 pub enum DiskId {
     D0,
     D1,
+    D2,
+    D3,
+    D4,
+    D5,
+    D6,
+    D7,
 }
 
-const DISK_IDS: &[DiskId] = &[DiskId::D0, DiskId::D1];
+const DISK_IDS: [DiskId; MAX_DISKS] = [
+    DiskId::D0,
+    DiskId::D1,
+    DiskId::D2,
+    DiskId::D3,
+    DiskId::D4,
+    DiskId::D5,
+    DiskId::D6,
+    DiskId::D7,
+];
+
+impl DiskId {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
 
-enum DiskResult {
+/// Outcome of a disk operation, the argument of its finished event.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum DiskResult {
     Ok,
     DiskNotPresent,
     DiskBusy,
+    DiskNotWriteable,
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum DiskOpKind {
+    Read,
+    Write,
 }
 
 pub struct DiskIdConvertError;
@@ -174,8 +440,7 @@ macro_rules! disk_id_from {
             
                 fn try_from(from: $num) -> Result<Self, Self::Error> {
                     match from {
-                        0 => Ok(DiskId::D0),
-                        1 => Ok(DiskId::D1),
+                        0..=7 => Ok(DISK_IDS[from as usize]),
                         _ => Err(DiskIdConvertError),
                     }
                 }
@@ -189,12 +454,30 @@ disk_id_from!(i8, u8, i16, u16, i32, u32, i64, u64, i128, u128, isize, usize);
 pub trait Storage<T>: Default {
     fn as_ref(&self) -> &T;
     fn as_mut(&mut self) -> &mut T;
+
+    /// Copies the bytes starting at `offset` to `buf`. The emulator
+    /// accesses disks only through `read` and `write`, so that storages
+    /// can implement them without keeping all the contents in one piece.
+    fn read(&self, offset: usize, buf: &mut [u8])
+    where
+        T: AsRef<[u8]>,
+    {
+        buf.copy_from_slice(&self.as_ref().as_ref()[offset..][..buf.len()]);
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8])
+    where
+        T: AsMut<[u8]>,
+    {
+        self.as_mut().as_mut()[offset..][..bytes.len()].copy_from_slice(bytes);
+    }
 }
 
 pub struct Disk<'a> {
     pub data: &'a mut [u8; DISK_SIZE],
     pub modified: bool,
     pub idle_time: u64,
+    pub read_only: bool,
 }
 
 struct DiskSlot<S> {
@@ -202,11 +485,13 @@ struct DiskSlot<S> {
     disk: SlotDisk,
 }
 
+#[derive(Copy, Clone)]
 enum SlotDisk {
     Missing,
     Present {
         modified: bool,
         idle_time: u64,
+        read_only: bool,
         running_op: Option<DiskOp>,
     }
 }
@@ -217,6 +502,24 @@ impl SlotDisk {
             *running_op = None;
         }
     }
+
+    // cycles until the next byte is transferred
+    fn idle_cycles(&self) -> u64 {
+        match self {
+            SlotDisk::Present { running_op: Some(DiskOp::Reading { delay, .. }), .. }
+            | SlotDisk::Present { running_op: Some(DiskOp::Writing { delay, .. }), .. } => delay - 1,
+            _ => u64::MAX,
+        }
+    }
+
+    fn skip(&mut self, cycles: u64) {
+        match self {
+            SlotDisk::Present { running_op: Some(DiskOp::Reading { delay, .. }), .. }
+            | SlotDisk::Present { running_op: Some(DiskOp::Writing { delay, .. }), .. } => *delay -= cycles,
+            SlotDisk::Present { idle_time, .. } => *idle_time = idle_time.saturating_add(cycles),
+            SlotDisk::Missing => {}
+        }
+    }
 }
 
 impl<S> DiskSlot<S> {
@@ -228,7 +531,7 @@ impl<S> DiskSlot<S> {
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Copy, Clone)]
 enum CpuState {
     Running,
     Waiting,
@@ -298,23 +601,58 @@ pub trait Tracer {
     fn on_instruction(&self, _address: u16, _instruction: Instruction) {}
     fn on_load(&self, _address: u16, _value: u16, _wide: bool) {}
     fn on_store(&self, _address: u16, _value: u16, _wide: bool) {}
+    /// Called for every cycle the CPU spends waiting for an event.
+    fn on_idle(&self) {}
+    fn on_console_output(&self, _byte: u8) {}
+    /// Called when `read` or `write` starts a transfer, `disk_ptr` is in bytes.
+    fn on_disk_op_start(&self, _disk: DiskId, _kind: DiskOpKind, _memory_ptr: u16, _disk_ptr: usize) {}
+    /// Called when a disk operation finishes, or right away with
+    /// the error if it couldn't start.
+    fn on_disk_op_finish(&self, _disk: DiskId, _result: DiskResult) {}
+    /// Called for every byte a disk read copies to memory, which
+    /// `on_store` doesn't see.
+    fn on_dma_store(&self, _disk: DiskId, _address: u16, _value: u8) {}
+    /// Called when `wait` or `poll` takes an event from the queue.
+    fn on_event(&self, _event: Event) {}
+    /// Called when `wait` puts the CPU in the waiting state.
+    fn on_wait_start(&self) {}
+    /// Called when the CPU stops waiting, after the event that ended
+    /// the wait is delivered or before a reset.
+    fn on_wait_end(&self) {}
+    fn on_halt(&self) {}
+    fn on_reset(&self) {}
+    /// Called when the bytes at `address` don't decode to a valid
+    /// instruction, before `on_instruction`.
+    fn on_invalid_instruction(&self, _address: u16) {}
 }
 
 pub struct NoopTracer;
 
 impl Tracer for NoopTracer {}
 
-pub struct Emulator<SM, SD, T = NoopTracer> {
+pub struct Emulator<SM, SD, T = NoopTracer, D = NoDevice> {
     tracer: T,
     memory: SM,
-    screen: [[u8; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    framebuffer: Framebuffer,
+    bus: Bus<D>,
     registers: Registers,
     instruction_pointer: u16,
     event_queue: EventQueue,
-    disk_slots: [DiskSlot<SD>; 2],
+    // the first `N` are connected, where `N` is the number of disks
+    // the emulator was created with
+    disk_slots: [Option<DiskSlot<SD>>; MAX_DISKS],
     cycles: u64,
-    time_to_refresh: u64,
     state: CpuState,
+    // set when `run_until` stopped on a breakpoint after devices
+    // were updated for the current cycle, but before the instruction ran
+    instruction_pending: bool,
+    // cycles `run` executed instructions in without ticking the devices,
+    // see `sync_devices`
+    deferred_ticks: u64,
+    debugger: debug::Debugger,
+    history: Option<History>,
+    instruction_cache: Option<InstructionCache>,
+    config: MachineConfig,
 }
 
 impl<SM, SD> Emulator<SM, SD, NoopTracer>
@@ -329,73 +667,196 @@ where
 
 impl<SM, SD, T> Emulator<SM, SD, T> {
     pub fn with_tracer(memory: SM, disks: [SD; 2], tracer: T) -> Self {
-        let [d0, d1] = disks;
+        Emulator::with_devices(memory, disks, tracer, NoDevice)
+    }
+}
+
+impl<SM, SD, T, D> Emulator<SM, SD, T, D> {
+    /// Creates an emulator with additional devices on the bus, which are
+    /// consulted after the built-in ones. Several devices can be combined
+    /// into one with tuples.
+    pub fn with_devices(memory: SM, disks: [SD; 2], tracer: T, devices: D) -> Self {
+        Emulator::with_config(MachineConfig::classic(), memory, disks, tracer, devices)
+    }
+
+    /// Creates an emulator for a machine with different timings or capacities.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a parameter of `config` is zero, the event queue
+    /// capacity exceeds `MAX_EVENT_QUEUE_CAPACITY` or there are
+    /// more than `MAX_DISKS` disks.
+    pub fn with_config<const N: usize>(
+        config: MachineConfig,
+        memory: SM,
+        disks: [SD; N],
+        tracer: T,
+        devices: D,
+    ) -> Self {
+        assert!(config.is_valid(), "invalid machine config {:?}", config);
+        assert!(N <= MAX_DISKS, "at most {} disk drives are supported", MAX_DISKS);
+        let mut disk_slots: [Option<DiskSlot<SD>>; MAX_DISKS] = Default::default();
+        for (slot, data) in disk_slots.iter_mut().zip(IntoIterator::into_iter(disks)) {
+            *slot = Some(DiskSlot::new(data));
+        }
+        let mut event_queue = EventQueue::new();
+        event_queue.capacity = config.event_queue_capacity;
+        event_queue.policy = config.overflow_policy;
         Emulator {
             tracer,
             memory,
-            screen: [[0; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            framebuffer: Framebuffer::new(config.screen_refresh_time),
+            bus: ((Timer::new(), (Console::new(), Sound::new())), devices),
             registers: Registers::new(),
             instruction_pointer: 0,
-            event_queue: EventQueue::new(),
-            disk_slots: [DiskSlot::new(d0), DiskSlot::new(d1)],
+            event_queue,
+            disk_slots,
             cycles: 0,
-            time_to_refresh: SCREEN_REFRESH_TIME,
             state: CpuState::Running,
+            instruction_pending: false,
+            deferred_ticks: 0,
+            debugger: debug::Debugger::new(),
+            history: None,
+            instruction_cache: None,
+            config,
         }
     }
+
+    /// Number of disk drives, the emulator was created with as many disks.
+    pub fn disk_drives(&self) -> usize {
+        self.disk_slots.iter().take_while(|slot| slot.is_some()).count()
+    }
 }
 
-impl<SM, SD, T> Emulator<SM, SD, T>
+impl<SM, SD, T, D> Emulator<SM, SD, T, D>
 where
     SM: Storage<[u8; MEMORY_SIZE]>,
     SD: Storage<[u8; DISK_SIZE]>,
     T: Tracer,
+    D: Device,
 {
+    fn events(&mut self) -> Events<'_> {
+        Events {
+            memory: self.memory.as_mut(),
+            queue: &mut self.event_queue,
+            history: &mut self.history,
+            instruction_cache: &mut self.instruction_cache,
+        }
+    }
+
     fn queue_event(&mut self, event: Event) {
-        self.event_queue.push(event);
+        self.events().push(event);
+    }
+
+    fn disk_op_failed(&mut self, id: DiskId, result: DiskResult) {
+        self.tracer.on_disk_op_finish(id, result);
+        self.queue_event(Event::disk_finished(id, result));
     }
 
-    fn disk_slot_mut(&mut self, id: DiskId) -> &mut DiskSlot<SD> {
-        match id {
-            DiskId::D0 => &mut self.disk_slots[0],
-            DiskId::D1 => &mut self.disk_slots[1],
+    fn disk_slot_mut(&mut self, id: DiskId) -> Option<&mut DiskSlot<SD>> {
+        self.disk_slots[id.index()].as_mut()
+    }
+
+    fn connected_slot_mut(&mut self, id: DiskId) -> &mut DiskSlot<SD> {
+        match self.disk_slot_mut(id) {
+            Some(slot) => slot,
+            None => panic!("disk drive {:?} isn't connected", id),
         }
     }
 
-    pub fn insert_disk(&mut self, id: DiskId) {
-        let slot = self.disk_slot_mut(id);
+    fn insert(&mut self, id: DiskId, read_only: bool) {
+        let slot = self.connected_slot_mut(id);
         if let SlotDisk::Missing = slot.disk {
             slot.disk = SlotDisk::Present {
                 modified: false,
                 idle_time: u64::max_value(),
+                read_only,
                 running_op: None,
             }
         }
     }
 
+    /// # Panics
+    ///
+    /// Panics if the drive isn't connected, same for
+    /// the other methods that insert or remove disks.
+    pub fn insert_disk(&mut self, id: DiskId) {
+        self.insert(id, false);
+    }
+
+    /// Inserts a write-protected disk, `write` to it fails with
+    /// "disk not writeable" error.
+    pub fn insert_read_only_disk(&mut self, id: DiskId) {
+        self.insert(id, true);
+    }
+
     pub fn remove_disk(&mut self, id: DiskId) {
-        let slot = self.disk_slot_mut(id);
+        let slot = self.connected_slot_mut(id);
         slot.disk = SlotDisk::Missing;
     }
 
     pub fn disk(&mut self, id: DiskId) -> Option<Disk<'_>> {
-        let slot = self.disk_slot_mut(id);
+        let slot = self.disk_slot_mut(id)?;
         match slot.disk {
             SlotDisk::Missing => None,
-            SlotDisk::Present { modified, idle_time, .. } => Some(Disk {
+            SlotDisk::Present { modified, idle_time, read_only, .. } => Some(Disk {
                 modified,
                 idle_time,
+                read_only,
                 data: slot.data.as_mut(),
             }),
         }
     }
 
+    /// Storage of the drive, whether or not a disk is inserted. Unlike
+    /// `disk` and `disk_slot` it doesn't need mutable access, which an
+    /// `OverlayDisk` would count as writing every block.
+    pub fn disk_storage(&self, id: DiskId) -> Option<&SD> {
+        self.disk_slots[id.index()].as_ref().map(|slot| &slot.data)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the drive isn't connected.
     pub fn disk_slot(&mut self, id: DiskId) -> &mut SD {
-        &mut self.disk_slot_mut(id).data
+        &mut self.connected_slot_mut(id).data
     }
 
     pub fn screen(&self) -> &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT] {
-        &self.screen
+        &self.framebuffer.screen
+    }
+
+    /// Bytes the guest wrote to the console since the output was last
+    /// cleared, at most `CONSOLE_BUFFER_SIZE` of them.
+    pub fn console_output(&self) -> &[u8] {
+        let ((_, (console, _)), _) = &self.bus;
+        console.output()
+    }
+
+    pub fn clear_console_output(&mut self) {
+        let ((_, (console, _)), _) = &mut self.bus;
+        console.len = 0;
+    }
+
+    /// Signed 16-bit mono samples produced since the buffer was last
+    /// cleared, one every `CYCLES_PER_SAMPLE` cycles. At most
+    /// `AUDIO_BUFFER_SIZE` samples are kept.
+    pub fn audio_samples(&self) -> &[i16] {
+        let ((_, (_, sound)), _) = &self.bus;
+        sound.samples()
+    }
+
+    pub fn clear_audio_samples(&mut self) {
+        let ((_, (_, sound)), _) = &mut self.bus;
+        sound.state.len = 0;
+    }
+
+    pub fn devices(&self) -> &D {
+        &self.bus.1
+    }
+
+    pub fn devices_mut(&mut self) -> &mut D {
+        &mut self.bus.1
     }
 
     pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
@@ -403,67 +864,88 @@ where
     }
 
     pub fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        self.clear_instruction_cache();
         self.memory.as_mut()
     }
 
+    fn clear_instruction_cache(&mut self) {
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.clear();
+        }
+    }
+
+    pub fn config(&self) -> MachineConfig {
+        MachineConfig {
+            event_queue_capacity: self.event_queue.capacity,
+            overflow_policy: self.event_queue.policy,
+            ..self.config
+        }
+    }
+
+    pub fn tracer(&self) -> &T {
+        &self.tracer
+    }
+
     pub fn is_running(&self) -> bool {
         self.state != CpuState::Halted
     }
 
+    pub fn registers(&self) -> Registers {
+        self.registers
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
+    }
+
+    pub fn instruction_pointer(&self) -> u16 {
+        self.instruction_pointer
+    }
+
+    pub fn set_instruction_pointer(&mut self, address: u16) {
+        self.instruction_pointer = address;
+    }
+
     // not inlining this allows copy_from_slice to be inlined here which
     // ends up eliminating a bunch of bound checks and formatting machinery
     #[inline(never)]
     pub fn reset(&mut self) {
-        for slot in &mut self.disk_slots {
-            if let SlotDisk::Present { running_op, .. } = &mut slot.disk {
-                *running_op = None;
-            }
+        if self.state == CpuState::Waiting {
+            self.tracer.on_wait_end();
+        }
+        self.tracer.on_reset();
+        for slot in self.disk_slots.iter_mut().flatten() {
+            slot.disk.finish_op();
         }
         for byte in &mut self.memory.as_mut()[..] {
             *byte = 0;
         }
-        self.screen = [[0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        self.framebuffer.reset();
+        self.bus.reset();
         self.registers = Registers::default();
         self.instruction_pointer = 0;
-        self.event_queue = EventQueue::new();
+        self.event_queue.clear();
         self.cycles = 0;
-        self.time_to_refresh = SCREEN_REFRESH_TIME;
         self.state = CpuState::Running;
-        if let SlotDisk::Present { idle_time, .. } = &mut self.disk_slots[0].disk {
-            self.memory.as_mut()[..DISK_OP_SIZE].copy_from_slice(
-                &self.disk_slots[0].data.as_ref()[..DISK_OP_SIZE],
-            );
+        self.instruction_pending = false;
+        #[cfg(any(test, feature = "std"))]
+        self.clear_history();
+        self.clear_instruction_cache();
+        if let Some(DiskSlot { data, disk: SlotDisk::Present { idle_time, .. } }) = &mut self.disk_slots[0] {
+            let size = self.config.disk_op_size;
+            data.read(0, &mut self.memory.as_mut()[..size]);
             *idle_time = 0;
         }
     }
 
-    fn read_byte(&mut self) -> u8 {
-        let byte = self.load(self.instruction_pointer);
-        self.instruction_pointer = self.instruction_pointer.wrapping_add(1);
-        byte
-    }
-
-    fn read_word(&mut self) -> u16 {
-        let low = u16::from(self.read_byte());
-        let high = u16::from(self.read_byte());
-        (high << 8) + low
-    }
-
-    fn refresh_screen(&mut self) {
-        for row in 0..SCREEN_HEIGHT {
-            for col in 0..SCREEN_WIDTH {
-                let offset = row * SCREEN_WIDTH + col;
-                let addr = SCREEN_POSITION + (offset as u16);
-                self.screen[row][col] = self.load(addr);
-            }
-        }
-        self.tracer.on_screen_refresh(&self.screen);
-    }
-    
     fn update_disk(
         disk_id: DiskId,
         memory: &mut [u8; MEMORY_SIZE],
         slot: &mut DiskSlot<SD>,
+        cycles_per_byte: u64,
+        history: &mut Option<History>,
+        instruction_cache: &mut Option<InstructionCache>,
+        tracer: &T,
     ) -> Option<Event> {
         match &mut slot.disk {
             SlotDisk::Present {
@@ -477,13 +959,22 @@ where
             } => {
                 *delay -= 1;
                 if *delay == 0 {
-                    *delay = CYCLES_PER_BYTE;
-                    memory[*memory_ptr % memory.len()] = slot.data.as_ref()[*disk_ptr % DISK_SIZE];
+                    *delay = cycles_per_byte;
+                    let addr = *memory_ptr % memory.len();
+                    if let Some(history) = history {
+                        history.memory(addr as u16, memory[addr]);
+                    }
+                    if let Some(cache) = instruction_cache {
+                        cache.invalidate(addr as u16);
+                    }
+                    slot.data.read(*disk_ptr % DISK_SIZE, &mut memory[addr..=addr]);
+                    tracer.on_dma_store(disk_id, addr as u16, memory[addr]);
                     *disk_ptr = disk_ptr.wrapping_add(1);
                     *memory_ptr = memory_ptr.wrapping_add(1);
                     *remaining -= 1;
                     if *remaining == 0 {
                         slot.disk.finish_op();
+                        tracer.on_disk_op_finish(disk_id, DiskResult::Ok);
                         Some(Event::disk_finished(disk_id, DiskResult::Ok))
                     } else {
                         None
@@ -503,13 +994,21 @@ where
             } => {
                 *delay -= 1;
                 if *delay == 0 {
-                    *delay = CYCLES_PER_BYTE;
-                    slot.data.as_mut()[*disk_ptr % DISK_SIZE] = memory[*memory_ptr % memory.len()];
+                    *delay = cycles_per_byte;
+                    let addr = *disk_ptr % DISK_SIZE;
+                    if let Some(history) = history {
+                        let mut old = [0];
+                        slot.data.read(addr, &mut old);
+                        history.disk(disk_id.index(), addr, old[0]);
+                    }
+                    let memory_addr = *memory_ptr % memory.len();
+                    slot.data.write(addr, &memory[memory_addr..=memory_addr]);
                     *disk_ptr = disk_ptr.wrapping_add(1);
                     *memory_ptr = memory_ptr.wrapping_add(1);
                     *remaining -= 1;
                     if *remaining == 0 {
                         slot.disk.finish_op();
+                        tracer.on_disk_op_finish(disk_id, DiskResult::Ok);
                         Some(Event::disk_finished(disk_id, DiskResult::Ok))
                     } else {
                         None
@@ -527,168 +1026,236 @@ where
     }
 
     pub fn cycle(&mut self) {
-        if self.time_to_refresh == 0 {
-            self.refresh_screen();
-            self.time_to_refresh = SCREEN_REFRESH_TIME;
-            self.event_queue.push(Event::screen_refresh());
+        if !self.instruction_pending {
+            self.start_cycle();
         }
+        self.instruction_pending = false;
+        self.execute();
+    }
+
+    fn start_cycle(&mut self) {
+        #[cfg(any(test, feature = "std"))]
+        self.save_frame();
 
-        self.time_to_refresh -= 1;
+        let refresh = self.framebuffer.time_to_refresh == 0;
+        if refresh {
+            if let Some(history) = &mut self.history {
+                history.screen(&self.framebuffer.screen);
+            }
+        }
+        let mut events = Events {
+            memory: self.memory.as_mut(),
+            queue: &mut self.event_queue,
+            history: &mut self.history,
+            instruction_cache: &mut self.instruction_cache,
+        };
+        self.framebuffer.tick(&mut events);
+        if refresh {
+            self.tracer.on_screen_refresh(&self.framebuffer.screen);
+        }
 
         for (index, &disk_id) in DISK_IDS.iter().enumerate() {
+            let slot = match &mut self.disk_slots[index] {
+                Some(slot) => slot,
+                None => break,
+            };
             if let Some(event) = Self::update_disk(
                 disk_id,
                 self.memory.as_mut(),
-                &mut self.disk_slots[index],
+                slot,
+                self.config.cycles_per_byte,
+                &mut self.history,
+                &mut self.instruction_cache,
+                &self.tracer,
             ) {
                 self.queue_event(event);
             }
         }
 
+        let mut events = Events {
+            memory: self.memory.as_mut(),
+            queue: &mut self.event_queue,
+            history: &mut self.history,
+            instruction_cache: &mut self.instruction_cache,
+        };
+        self.bus.tick(&mut events);
+
         self.cycles += 1;
 
         if self.state == CpuState::Waiting {
             if let Some(event) = self.event_queue.pop() {
+                self.tracer.on_event(event);
                 self.registers.a = event.id;
                 self.registers.b = event.arg;
                 self.state = CpuState::Running;
+                self.tracer.on_wait_end();
             }
         }
-        
+    }
+
+    fn execute(&mut self) -> Option<Instruction> {
         if self.state == CpuState::Running {
             self.tracer.register_values(self.registers);
             let address = self.instruction_pointer;
-            let instruction = self.decode_instruction();
+            let memory = self.memory.as_ref();
+            let (instruction, len) = match &mut self.instruction_cache {
+                Some(cache) => cache.fetch(memory, address),
+                None => decode::decode_with(|offset| {
+                    memory[usize::from(address.wrapping_add(offset as u16))]
+                }),
+            };
+            self.instruction_pointer = address.wrapping_add(len as u16);
+            if instruction == Instruction::Invalid {
+                self.tracer.on_invalid_instruction(address);
+            }
             self.tracer.on_instruction(address, instruction);
             self.apply_instruction(instruction);
+            Some(instruction)
+        } else {
+            if self.state == CpuState::Waiting {
+                self.tracer.on_idle();
+            }
+            None
         }
     }
 
+    /// Runs for `cycles` cycles. Gives the same result as calling `cycle`
+    /// that many times, but skips over cycles in which neither the CPU nor
+    /// any device has anything to do.
     pub fn run(&mut self, cycles: u64) {
-        for _ in 0..cycles {
+        let mut remaining = cycles;
+        if self.instruction_pending && remaining > 0 {
             self.cycle();
+            remaining -= 1;
+        }
+        while remaining > 0 {
+            let idle = self.idle_cycles().min(remaining);
+            if idle == 0 {
+                self.cycle();
+                remaining -= 1;
+            } else if self.state == CpuState::Running {
+                remaining -= self.run_batch(idle);
+            } else if self.state == CpuState::Halted || self.event_queue.is_empty() {
+                self.skip_devices(idle);
+                self.cycles += idle;
+                if self.state == CpuState::Waiting {
+                    for _ in 0..idle {
+                        self.tracer.on_idle();
+                    }
+                }
+                remaining -= idle;
+            } else {
+                self.cycle();
+                remaining -= 1;
+            }
         }
     }
 
-    pub fn cycles(&self) -> u64 {
-        self.cycles
+    // runs instructions for at most `cycles` cycles without ticking the
+    // devices, stops early when the CPU stops running or an instruction
+    // touches a device. Returns the number of cycles run.
+    fn run_batch(&mut self, cycles: u64) -> u64 {
+        let mut done = 0;
+        while done < cycles {
+            self.deferred_ticks += 1;
+            self.cycles += 1;
+            done += 1;
+            self.execute();
+            // `sync_devices` was called, device deadlines may have changed
+            if self.deferred_ticks == 0 || self.state != CpuState::Running {
+                break;
+            }
+        }
+        self.sync_devices();
+        done
     }
 
-    fn decode_instruction(&mut self) -> Instruction {
-        let x = self.read_byte();
-        let (ro_1, ro_2, ro_s) = self.register_operand();
-        let (oo_1, oo_2, oo_s) = self.two_operands();
-        let r = self.decode_register(x);
-        let (o, o_s) = self.one_operand(x);
-        match x {
-            0b0000_0000 => Instruction::Nop,
-            0b0000_0001 => Instruction::Ret,
-            0b0000_0010 => Instruction::Wait,
-            0b0000_0011 => Instruction::Poll,
-            0b0000_0100 => Instruction::Halt,
-            0b1000_0000 => { self.instruction_pointer = ro_s; Instruction::Mov(ro_1, ro_2) }
-            0b1000_0001 => { self.instruction_pointer = ro_s; Instruction::Add(ro_1, ro_2) }
-            0b1000_0010 => { self.instruction_pointer = ro_s; Instruction::Sub(ro_1, ro_2) }
-            0b1000_0011 => { self.instruction_pointer = ro_s; Instruction::Xor(ro_1, ro_2) }
-            0b1000_0100 => { self.instruction_pointer = ro_s; Instruction::And(ro_1, ro_2) }
-            0b1000_0101 => { self.instruction_pointer = ro_s; Instruction::Or(ro_1, ro_2) }
-            0b1000_0110 => { self.instruction_pointer = ro_s; Instruction::Shl(ro_1, ro_2) }
-            0b1000_0111 => { self.instruction_pointer = ro_s; Instruction::Shr(ro_1, ro_2) }
-            0b1000_1000 => { self.instruction_pointer = ro_s; Instruction::Cmp(ro_1, ro_2) }
-            0b1001_0000 ..= 0b1001_0010 => self.decode_load(x, Instruction::Load),
-            0b1001_0100 ..= 0b1001_0110 => self.decode_load(x, Instruction::Loadw),
-            0b1001_1000 ..= 0b1001_1010 => self.decode_store(x, Instruction::Store),
-            0b1001_1100 ..= 0b1001_1110 => self.decode_store(x, Instruction::Storew),
-            0b0001_0000 ..= 0b0001_0111 => Instruction::Not(r),
-            0b0010_0000 ..= 0b0010_0111 => Instruction::Neg(r),
-            0b0011_0000 ..= 0b0011_0111 => Instruction::Pop(r),
-            0b0100_0000 ..= 0b0100_1111 => { self.instruction_pointer = o_s; Instruction::Push(o) }
-            0b0101_0000 ..= 0b0101_1111 => { self.instruction_pointer = o_s; Instruction::Jmp(o) }
-            0b0110_0000 ..= 0b0110_1111 => { self.instruction_pointer = o_s; Instruction::Call(o) }
-            0b1010_0000 => { self.instruction_pointer = ro_s; Instruction::Jez(ro_1, ro_2) }
-            0b1010_0001 => { self.instruction_pointer = ro_s; Instruction::Jnz(ro_1, ro_2) }
-            0b1010_0010 => { self.instruction_pointer = ro_s; Instruction::Jl(ro_1, ro_2) }
-            0b1010_0011 => { self.instruction_pointer = ro_s; Instruction::Jg(ro_1, ro_2) }
-            0b1010_0100 => { self.instruction_pointer = ro_s; Instruction::Jle(ro_1, ro_2) }
-            0b1010_0101 => { self.instruction_pointer = ro_s; Instruction::Jge(ro_1, ro_2) }
-            0b1111_0000 => { self.instruction_pointer = oo_s; Instruction::Read(DiskId::D0, oo_1, oo_2) }
-            0b1111_0001 => { self.instruction_pointer = oo_s; Instruction::Read(DiskId::D1, oo_1, oo_2) }
-            0b1111_1000 => { self.instruction_pointer = oo_s; Instruction::Write(DiskId::D0, oo_1, oo_2) }
-            0b1111_1001 => { self.instruction_pointer = oo_s; Instruction::Write(DiskId::D1, oo_1, oo_2) }
-            _ => Instruction::Invalid,
+    // number of following cycles in which no device queues an event or
+    // changes memory
+    fn idle_cycles(&self) -> u64 {
+        // history needs a frame for every cycle
+        if self.history.is_some() {
+            return 0;
         }
+        let disks = self.disk_slots.iter().flatten().map(|slot| slot.disk.idle_cycles());
+        disks
+            .chain([self.framebuffer.idle_cycles(), self.bus.idle_cycles()].iter().copied())
+            .min()
+            .unwrap_or(u64::MAX)
     }
 
-    fn one_operand(&mut self, x: u8) -> (Operand, u16) {
-        let old_ip = self.instruction_pointer;
-        let operand = self.decode_operand(x & 0b1111);
-        let ip = self.instruction_pointer;
-        self.instruction_pointer = old_ip;
-        (operand, ip)
-    }
-
-    fn register_operand(&mut self) -> (Register, Operand, u16) {
-        let old_ip = self.instruction_pointer;
-        let x = self.read_byte();
-        let reg = self.decode_register((x >> 4) & 0b111);
-        let op = self.decode_operand(x & 0b1111);
-        let ip = self.instruction_pointer;
-        self.instruction_pointer = old_ip;
-        (reg, op, ip)
-    }
-
-    fn two_operands(&mut self) -> (Operand, Operand, u16) {
-        let old_ip = self.instruction_pointer;
-        let x = self.read_byte();
-        let op1 = self.decode_operand((x >> 4) & 0b1111);
-        let op2 = self.decode_operand(x & 0b1111);
-        let ip = self.instruction_pointer;
-        self.instruction_pointer = old_ip;
-        (op1, op2, ip)
-    }
-
-    fn decode_load(&mut self, b: u8, f: impl FnOnce(Register, Address) -> Instruction) -> Instruction {
-        let x = self.read_byte();
-        let offset = match b & 0b11 {
-            1 => u16::from(self.read_byte()),
-            2 => self.read_word(),
-            _ => 0,
-        };
-        let reg = self.decode_register((x >> 4) & 0b111);
-        let operand = self.decode_operand(x & 0b1111);
-        f(reg, Address { operand, offset })
+    fn skip_devices(&mut self, cycles: u64) {
+        let memory = self.memory.as_ref();
+        self.framebuffer.skip(memory, cycles);
+        for slot in self.disk_slots.iter_mut().flatten() {
+            slot.disk.skip(cycles);
+        }
+        self.bus.skip(memory, cycles);
     }
 
-    fn decode_store(&mut self, b: u8, f: impl FnOnce(Operand, Address) -> Instruction) -> Instruction {
-        let x = self.read_byte();
-        let offset = match b & 0b11 {
-            1 => u16::from(self.read_byte()),
-            2 => self.read_word(),
-            _ => 0,
-        };
-        let op = self.decode_operand((x >> 4) & 0b1111);
-        let operand = self.decode_operand(x & 0b1111);
-        f(op, Address { operand, offset })
+    // catches devices up with the cycles `run_batch` executed, must be
+    // called before an instruction interacts with a device
+    fn sync_devices(&mut self) {
+        if self.deferred_ticks != 0 {
+            self.skip_devices(self.deferred_ticks);
+            self.deferred_ticks = 0;
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
-    fn load(&self, addr: u16) -> u8 {
-        self.memory.as_ref()[addr as usize]
+    /// Runs for `cycles` cycles and returns the audio produced meanwhile.
+    pub fn render_audio(&mut self, cycles: u64) -> &[i16] {
+        self.clear_audio_samples();
+        self.run(cycles);
+        self.audio_samples()
     }
 
-    fn load_word(&self, addr: u16) -> u16 {
-        let low = self.memory.as_ref()[addr as usize];
-        let high = self.memory.as_ref()[addr.wrapping_add(1) as usize];
+    fn load(&mut self, addr: u16) -> u8 {
+        let memory = self.memory.as_ref();
+        if self.framebuffer.contains(addr) {
+            self.framebuffer.load(memory, addr)
+        } else if self.bus.contains(addr) {
+            self.sync_devices();
+            self.bus.load(self.memory.as_ref(), addr)
+        } else {
+            memory[addr as usize]
+        }
+    }
+
+    fn load_word(&mut self, addr: u16) -> u16 {
+        let low = self.load(addr);
+        let high = self.load(addr.wrapping_add(1));
         u16::from_le_bytes([low, high])
     }
 
     fn store(&mut self, addr: u16, value: u8) {
-        self.memory.as_mut()[addr as usize] = value;
+        let memory = self.memory.as_mut();
+        if let Some(history) = &mut self.history {
+            history.memory(addr, memory[addr as usize]);
+        }
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.invalidate(addr);
+        }
+        if self.framebuffer.contains(addr) {
+            self.framebuffer.store(memory, addr, value);
+        } else if self.bus.contains(addr) {
+            self.sync_devices();
+            self.bus.store(self.memory.as_mut(), addr, value);
+            if addr == CONSOLE_POSITION {
+                self.tracer.on_console_output(value);
+            }
+        } else {
+            memory[addr as usize] = value;
+        }
     }
 
     fn store_word(&mut self, addr: u16, value: u16) {
         let [low, high] = value.to_le_bytes();
-        self.memory.as_mut()[addr as usize] = low;
-        self.memory.as_mut()[addr.wrapping_add(1) as usize] = high;
+        self.store(addr, low);
+        self.store(addr.wrapping_add(1), high);
     }
 
     fn apply_instruction(&mut self, instruction: Instruction) {
@@ -696,11 +1263,17 @@ where
             Instruction::Nop => {}
             Instruction::Ret => {
                 self.registers.s = self.registers.s.wrapping_add(2);
-                self.instruction_pointer = self.load_word(self.registers.s);
+                let value = self.load_word(self.registers.s);
+                self.debugger.on_load(self.registers.s, value, true);
+                self.instruction_pointer = value;
+            }
+            Instruction::Wait => {
+                self.state = CpuState::Waiting;
+                self.tracer.on_wait_start();
             }
-            Instruction::Wait => self.state = CpuState::Waiting,
             Instruction::Poll => {
                 if let Some(event) = self.event_queue.pop() {
+                    self.tracer.on_event(event);
                     self.registers.a = event.id;
                     self.registers.b = event.arg;
                 } else {
@@ -708,19 +1281,27 @@ where
                     self.registers.b = 0;
                 }
             }
-            Instruction::Halt => self.state = CpuState::Halted,
+            Instruction::Halt => {
+                self.state = CpuState::Halted;
+                self.tracer.on_halt();
+            }
             Instruction::Not(a) => self.registers.set(a, !self.eval(a)),
             Instruction::Neg(a) => self.registers.set(a, self.eval(a).wrapping_neg()),
             Instruction::Pop(a) => {
                 self.registers.s = self.registers.s.wrapping_add(2);
-                self.registers.set(a, self.load_word(self.registers.s));
+                let value = self.load_word(self.registers.s);
+                self.debugger.on_load(self.registers.s, value, true);
+                self.registers.set(a, value);
             }
             Instruction::Push(a) => {
-                self.store_word(self.registers.s, self.eval(a));
+                let value = self.eval(a);
+                self.debugger.on_store(self.registers.s, value, true);
+                self.store_word(self.registers.s, value);
                 self.registers.s = self.registers.s.wrapping_sub(2);
             }
             Instruction::Jmp(a) => self.instruction_pointer = self.eval(a),
             Instruction::Call(a) => {
+                self.debugger.on_store(self.registers.s, self.instruction_pointer, true);
                 self.store_word(self.registers.s, self.instruction_pointer);
                 self.instruction_pointer = self.eval(a);
                 self.registers.s = self.registers.s.wrapping_sub(2);
@@ -762,24 +1343,28 @@ where
                 let addr = self.eval(b);
                 let value = u16::from(self.load(addr));
                 self.tracer.on_load(addr, value, false);
+                self.debugger.on_load(addr, value, false);
                 self.registers.set(a, value);
             }
             Instruction::Loadw(a, b) => {
                 let addr = self.eval(b);
                 let value = self.load_word(addr);
                 self.tracer.on_load(addr, value, true);
+                self.debugger.on_load(addr, value, true);
                 self.registers.set(a, value);
             }
             Instruction::Store(a, b) => {
                 let addr = self.eval(b);
                 let value = self.eval(a).to_le_bytes()[0];
                 self.tracer.on_store(addr, u16::from(value), false);
+                self.debugger.on_store(addr, u16::from(value), false);
                 self.store(addr, value);
             }
             Instruction::Storew(a, b) => {
                 let addr = self.eval(b);
                 let value = self.eval(a);
                 self.tracer.on_store(addr, value, true);
+                self.debugger.on_store(addr, value, true);
                 self.store_word(addr, value);
             }
             Instruction::Jez(a, d) => {
@@ -814,83 +1399,55 @@ where
             }
             Instruction::Read(id, memory_ptr, disk_ptr) => {
                 let memory_ptr = usize::from(self.eval(memory_ptr));
-                let disk_ptr = usize::from(self.eval(disk_ptr)) * 16;
-                if let SlotDisk::Present { running_op, idle_time, .. } = &mut self.disk_slot_mut(id).disk {
+                let disk_ptr = usize::from(self.eval(disk_ptr)) * DISK_BLOCK_SIZE;
+                let config = self.config;
+                self.sync_devices();
+                if let Some(SlotDisk::Present { running_op, idle_time, .. }) = self.disk_slot_mut(id).map(|slot| &mut slot.disk) {
                     if running_op.is_none() {
                         *idle_time = 0;
                         *running_op = Some(DiskOp::Reading {
                             memory_ptr,
                             disk_ptr,
-                            remaining: DISK_OP_SIZE,
-                            delay: CYCLES_PER_BYTE,
+                            remaining: config.disk_op_size,
+                            delay: config.cycles_per_byte,
                         });
+                        self.tracer.on_disk_op_start(id, DiskOpKind::Read, memory_ptr as u16, disk_ptr);
                     } else {
-                        self.queue_event(Event::disk_finished(id, DiskResult::DiskBusy));
+                        self.disk_op_failed(id, DiskResult::DiskBusy);
                     }
                 } else {
-                    self.queue_event(Event::disk_finished(id, DiskResult::DiskNotPresent));
+                    self.disk_op_failed(id, DiskResult::DiskNotPresent);
                 }
             }
             Instruction::Write(id, memory_ptr, disk_ptr) => {
                 let memory_ptr = usize::from(self.eval(memory_ptr));
-                let disk_ptr = usize::from(self.eval(disk_ptr)) * 16;
-                if let SlotDisk::Present { running_op, idle_time, modified } = &mut self.disk_slot_mut(id).disk {
-                    if running_op.is_none() {
+                let disk_ptr = usize::from(self.eval(disk_ptr)) * DISK_BLOCK_SIZE;
+                let config = self.config;
+                self.sync_devices();
+                if let Some(SlotDisk::Present { running_op, idle_time, modified, read_only }) = self.disk_slot_mut(id).map(|slot| &mut slot.disk) {
+                    if *read_only {
+                        self.disk_op_failed(id, DiskResult::DiskNotWriteable);
+                    } else if running_op.is_none() {
                         *modified = true;
                         *idle_time = 0;
                         *running_op = Some(DiskOp::Writing {
                             memory_ptr,
                             disk_ptr,
-                            remaining: DISK_OP_SIZE,
-                            delay: CYCLES_PER_BYTE,
+                            remaining: config.disk_op_size,
+                            delay: config.cycles_per_byte,
                         });
+                        self.tracer.on_disk_op_start(id, DiskOpKind::Write, memory_ptr as u16, disk_ptr);
                     } else {
-                        self.queue_event(Event::disk_finished(id, DiskResult::DiskBusy));
+                        self.disk_op_failed(id, DiskResult::DiskBusy);
                     }
                 } else {
-                    self.queue_event(Event::disk_finished(id, DiskResult::DiskNotPresent));
+                    self.disk_op_failed(id, DiskResult::DiskNotPresent);
                 }
             }
             Instruction::Invalid => {}
         }
     }
 
-    fn decode_register(&mut self, bits: u8) -> Register {
-        match bits & 0b111 {
-            0b000 => Register::A,
-            0b001 => Register::B,
-            0b010 => Register::C,
-            0b011 => Register::D,
-            0b100 => Register::I,
-            0b101 => Register::J,
-            0b110 => Register::P,
-            0b111 => Register::S,
-            _ => unreachable!(),
-        }
-    }
-
-    fn decode_operand(&mut self, bits: u8) -> Operand {
-        match bits & 0b1111 {
-            0b000 => Operand::Register(Register::A),
-            0b001 => Operand::Register(Register::B),
-            0b010 => Operand::Register(Register::C),
-            0b011 => Operand::Register(Register::D),
-            0b100 => Operand::Register(Register::I),
-            0b101 => Operand::Register(Register::J),
-            0b110 => Operand::Register(Register::P),
-            0b111 => Operand::Register(Register::S),
-            0b1000 => Operand::Word(0),
-            0b1001 => Operand::Word(1),
-            0b1010 => Operand::Word(2),
-            0b1011 => Operand::Word(3),
-            0b1100 => Operand::Word(4),
-            0b1101 => Operand::Word(u16::from(self.read_byte())),
-            0b1110 => Operand::Word(self.read_word()),
-            0b1111 => Operand::Word(0xffff),
-            _ => unreachable!(),
-        }
-    }
-
     pub fn key_up(&mut self, key: u16) {
         self.queue_event(Event::key_up(key));
     }
@@ -898,13 +1455,44 @@ where
     pub fn key_down(&mut self, key: u16) {
         self.queue_event(Event::key_down(key));
     }
+
+    pub fn console_input(&mut self, byte: u8) {
+        self.queue_event(Event::console_input(byte));
+    }
+
+    pub fn event_queue_capacity(&self) -> usize {
+        self.event_queue.capacity
+    }
+
+    /// Changes how many events can wait in the queue, up to
+    /// `MAX_EVENT_QUEUE_CAPACITY`. If more are already queued,
+    /// the oldest ones are lost.
+    pub fn set_event_queue_capacity(&mut self, capacity: usize) {
+        self.event_queue.set_capacity(capacity);
+        #[cfg(any(test, feature = "std"))]
+        self.clear_history();
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.event_queue.policy
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.event_queue.policy = policy;
+    }
+
+    /// Number of events dropped because the queue was full. The guest
+    /// learns about them through an events lost event instead.
+    pub fn events_lost(&self) -> u64 {
+        self.event_queue.lost
+    }
 }
 
 trait Eval<T> {
     fn eval(&self, expr: T) -> u16;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Register {
     A,
     B,
@@ -932,13 +1520,13 @@ impl fmt::Display for Register {
     }
 }
 
-impl<SM, SD, T> Eval<Register> for Emulator<SM, SD, T> {
+impl<SM, SD, T, D> Eval<Register> for Emulator<SM, SD, T, D> {
     fn eval(&self, register: Register) -> u16 {
         self.registers.get(register)
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Operand {
     Register(Register),
     Word(u16),
@@ -953,7 +1541,7 @@ impl fmt::Display for Operand {
     }
 }
 
-impl<SM, SD, T> Eval<Operand> for Emulator<SM, SD, T> {
+impl<SM, SD, T, D> Eval<Operand> for Emulator<SM, SD, T, D> {
     fn eval(&self, operand: Operand) -> u16 {
         match operand {
             Operand::Register(r) => self.eval(r),
@@ -962,7 +1550,7 @@ impl<SM, SD, T> Eval<Operand> for Emulator<SM, SD, T> {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub struct Address {
     pub operand: Operand,
     pub offset: u16,
@@ -978,13 +1566,13 @@ impl fmt::Display for Address {
     }
 }
 
-impl<SM, SD, T> Eval<Address> for Emulator<SM, SD, T> {
+impl<SM, SD, T, D> Eval<Address> for Emulator<SM, SD, T, D> {
     fn eval(&self, address: Address) -> u16 {
         self.eval(address.operand).wrapping_add(address.offset)
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Instruction {
     Nop,
     Ret,
@@ -1054,10 +1642,8 @@ impl fmt::Display for Instruction {
             Instruction::Jg(x, d) => write!(f, "jg {}, {}", x, d),
             Instruction::Jle(x, d) => write!(f, "jle {}, {}", x, d),
             Instruction::Jge(x, d) => write!(f, "jge {}, {}", x, d),
-            Instruction::Read(DiskId::D0, a, b) => write!(f, "read0 {}, {}", a, b),
-            Instruction::Read(DiskId::D1, a, b) => write!(f, "read1 {}, {}", a, b),
-            Instruction::Write(DiskId::D0, a, b) => write!(f, "write0 {}, {}", a, b),
-            Instruction::Write(DiskId::D1, a, b) => write!(f, "write1 {}, {}", a, b),
+            Instruction::Read(d, a, b) => write!(f, "read{} {}, {}", d.index(), a, b),
+            Instruction::Write(d, a, b) => write!(f, "write{} {}, {}", d.index(), a, b),
             Instruction::Invalid => write!(f, "???"),
         }
     }
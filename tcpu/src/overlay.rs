@@ -0,0 +1,178 @@
+use std::cell::OnceCell;
+use std::sync::Arc;
+use super::{DiskMemory, Storage, DISK_BLOCK_SIZE, DISK_SIZE};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = DISK_SIZE / PAGE_SIZE;
+const BLOCKS: usize = DISK_SIZE / DISK_BLOCK_SIZE;
+
+/// Disk storage that keeps writes apart from a shared base image, so that
+/// many emulators can boot from one base. Written 4 KiB pages are copied
+/// from the base on their first write, and every written block is tracked,
+/// even if it was written with the bytes it already held.
+///
+/// The emulator only uses `read` and `write`. `as_ref` and `as_mut` need
+/// the contents in one piece, which copies the whole disk once there are
+/// any writes, and as writes through `as_mut` can't be tracked, it counts
+/// every block as written.
+pub struct OverlayDisk {
+    base: Arc<DiskMemory>,
+    // written pages by page index
+    pages: Vec<Option<Box<[u8; PAGE_SIZE]>>>,
+    // one bit per written block
+    written: Vec<u64>,
+    // all of the contents, built on the first `as_ref` or `as_mut` after
+    // a write, takes over from `pages` once built
+    merged: OnceCell<DiskMemory>,
+}
+
+impl Default for OverlayDisk {
+    fn default() -> Self {
+        OverlayDisk::new(Arc::new(DiskMemory::default()))
+    }
+}
+
+impl OverlayDisk {
+    pub fn new(base: Arc<DiskMemory>) -> Self {
+        OverlayDisk {
+            base,
+            pages: vec![None; PAGES],
+            written: vec![0; BLOCKS / 64],
+            merged: OnceCell::new(),
+        }
+    }
+
+    pub fn base(&self) -> &Arc<DiskMemory> {
+        &self.base
+    }
+
+    fn base_data(&self) -> &[u8; DISK_SIZE] {
+        Storage::as_ref(&*self.base)
+    }
+
+    fn is_written(&self, block: usize) -> bool {
+        self.written[block / 64] & (1 << (block % 64)) != 0
+    }
+
+    /// Indices of the written blocks, in order.
+    pub fn written_blocks(&self) -> Vec<usize> {
+        (0..BLOCKS).filter(|&block| self.is_written(block)).collect()
+    }
+
+    /// Contents of the written blocks, by block index.
+    pub fn delta(&self) -> Vec<(usize, [u8; DISK_BLOCK_SIZE])> {
+        self.written_blocks()
+            .into_iter()
+            .map(|block| {
+                let mut bytes = [0; DISK_BLOCK_SIZE];
+                self.read(block * DISK_BLOCK_SIZE, &mut bytes);
+                (block, bytes)
+            })
+            .collect()
+    }
+
+    /// Writes blocks from a `delta` over the current contents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a block index is past the end of the disk.
+    pub fn apply_delta(&mut self, delta: &[(usize, [u8; DISK_BLOCK_SIZE])]) {
+        for (block, bytes) in delta {
+            self.write(block * DISK_BLOCK_SIZE, bytes);
+        }
+    }
+
+    /// Drops all writes.
+    pub fn discard(&mut self) {
+        *self = OverlayDisk::new(self.base.clone());
+    }
+
+    /// Returns the base with the writes applied.
+    pub fn merge(mut self) -> DiskMemory {
+        match self.merged.take() {
+            Some(merged) => merged,
+            None => self.build_merged(),
+        }
+    }
+
+    fn build_merged(&self) -> DiskMemory {
+        let mut merged = DiskMemory::default();
+        merged.as_mut().copy_from_slice(self.base_data());
+        for (index, page) in self.pages.iter().enumerate() {
+            if let Some(page) = page {
+                merged.as_mut()[(index * PAGE_SIZE)..][..PAGE_SIZE].copy_from_slice(&page[..]);
+            }
+        }
+        merged
+    }
+}
+
+impl Storage<[u8; DISK_SIZE]> for OverlayDisk {
+    fn as_ref(&self) -> &[u8; DISK_SIZE] {
+        if self.merged.get().is_none() && self.pages.iter().all(Option::is_none) {
+            return self.base_data();
+        }
+        self.merged.get_or_init(|| self.build_merged()).as_ref()
+    }
+
+    fn as_mut(&mut self) -> &mut [u8; DISK_SIZE] {
+        if self.merged.get().is_none() {
+            self.merged = OnceCell::from(self.build_merged());
+        }
+        self.pages = vec![None; PAGES];
+        self.written = vec![!0; BLOCKS / 64];
+        self.merged.get_mut().unwrap().as_mut()
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        if let Some(merged) = self.merged.get() {
+            merged.read(offset, buf);
+            return;
+        }
+        // split at page boundaries
+        let mut position = offset;
+        let mut done = 0;
+        while done < buf.len() {
+            let page_offset = position % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(buf.len() - done);
+            let source = match &self.pages[position / PAGE_SIZE] {
+                Some(page) => &page[page_offset..][..len],
+                None => &self.base_data()[position..][..len],
+            };
+            buf[done..][..len].copy_from_slice(source);
+            done += len;
+            position += len;
+        }
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) {
+        assert!(offset + bytes.len() <= DISK_SIZE, "write past the end of the disk");
+        if !bytes.is_empty() {
+            let last = (offset + bytes.len() - 1) / DISK_BLOCK_SIZE;
+            for block in (offset / DISK_BLOCK_SIZE)..=last {
+                self.written[block / 64] |= 1 << (block % 64);
+            }
+        }
+        if let Some(merged) = self.merged.get_mut() {
+            merged.write(offset, bytes);
+            return;
+        }
+        // split at page boundaries
+        let mut position = offset;
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let index = position / PAGE_SIZE;
+            let page_offset = position % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(rest.len());
+            let base = &self.base;
+            let page = self.pages[index].get_or_insert_with(|| {
+                let mut page = Box::new([0; PAGE_SIZE]);
+                page.copy_from_slice(&Storage::as_ref(&**base)[(index * PAGE_SIZE)..][..PAGE_SIZE]);
+                page
+            });
+            page[page_offset..][..len].copy_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            position += len;
+        }
+    }
+}
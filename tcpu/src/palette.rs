@@ -0,0 +1,87 @@
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Size of the buffer `screen_to_rgba` fills, 4 bytes per pixel.
+pub const RGBA_SCREEN_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 4;
+
+// computed the same way as `getColor` in web/index.ts: bits 4-7 are the hue,
+// bit 3 the saturation and bits 0-2 the value, hue 15 is a gray scale instead
+static PALETTE: [(u8, u8, u8); 256] = [
+    (0, 0, 0), (44, 27, 27), (83, 50, 50), (119, 71, 71),
+    (154, 92, 92), (188, 113, 113), (222, 133, 133), (255, 153, 153),
+    (0, 0, 0), (44, 4, 4), (83, 8, 8), (119, 12, 12),
+    (154, 15, 15), (188, 19, 19), (222, 22, 22), (255, 25, 25),
+    (0, 0, 0), (44, 34, 27), (83, 63, 50), (119, 90, 71),
+    (154, 117, 92), (188, 143, 113), (222, 169, 133), (255, 194, 153),
+    (0, 0, 0), (44, 20, 4), (83, 38, 8), (119, 55, 12),
+    (154, 71, 15), (188, 87, 19), (222, 102, 22), (255, 117, 25),
+    (0, 0, 0), (44, 41, 27), (83, 76, 50), (119, 109, 71),
+    (154, 142, 92), (188, 173, 113), (222, 204, 133), (255, 235, 153),
+    (0, 0, 0), (44, 36, 4), (83, 68, 8), (119, 98, 12),
+    (154, 126, 15), (188, 154, 19), (222, 182, 22), (255, 209, 25),
+    (0, 0, 0), (41, 44, 27), (76, 83, 50), (109, 119, 71),
+    (142, 154, 92), (173, 188, 113), (204, 222, 133), (235, 255, 153),
+    (0, 0, 0), (36, 44, 4), (68, 83, 8), (98, 119, 12),
+    (126, 154, 15), (154, 188, 19), (182, 222, 22), (209, 255, 25),
+    (0, 0, 0), (34, 44, 27), (63, 83, 50), (90, 119, 71),
+    (117, 154, 92), (143, 188, 113), (169, 222, 133), (194, 255, 153),
+    (0, 0, 0), (20, 44, 4), (38, 83, 8), (55, 119, 12),
+    (71, 154, 15), (87, 188, 19), (102, 222, 22), (117, 255, 25),
+    (0, 0, 0), (27, 44, 27), (50, 83, 50), (71, 119, 71),
+    (92, 154, 92), (113, 188, 113), (133, 222, 133), (153, 255, 153),
+    (0, 0, 0), (4, 44, 4), (8, 83, 8), (12, 119, 12),
+    (15, 154, 15), (19, 188, 19), (22, 222, 22), (25, 255, 25),
+    (0, 0, 0), (27, 44, 34), (50, 83, 63), (71, 119, 90),
+    (92, 154, 117), (113, 188, 143), (133, 222, 169), (153, 255, 194),
+    (0, 0, 0), (4, 44, 20), (8, 83, 38), (12, 119, 55),
+    (15, 154, 71), (19, 188, 87), (22, 222, 102), (25, 255, 117),
+    (0, 0, 0), (27, 44, 41), (50, 83, 76), (71, 119, 109),
+    (92, 154, 142), (113, 188, 173), (133, 222, 204), (153, 255, 235),
+    (0, 0, 0), (4, 44, 36), (8, 83, 68), (12, 119, 98),
+    (15, 154, 126), (19, 188, 154), (22, 222, 182), (25, 255, 209),
+    (0, 0, 0), (27, 41, 44), (50, 76, 83), (71, 109, 119),
+    (92, 142, 154), (113, 173, 188), (133, 204, 222), (153, 235, 255),
+    (0, 0, 0), (4, 36, 44), (8, 68, 83), (12, 98, 119),
+    (15, 126, 154), (19, 154, 188), (22, 182, 222), (25, 209, 255),
+    (0, 0, 0), (27, 34, 44), (50, 63, 83), (71, 90, 119),
+    (92, 117, 154), (113, 143, 188), (133, 169, 222), (153, 194, 255),
+    (0, 0, 0), (4, 20, 44), (8, 38, 83), (12, 55, 119),
+    (15, 71, 154), (19, 87, 188), (22, 102, 222), (25, 117, 255),
+    (0, 0, 0), (27, 27, 44), (50, 50, 83), (71, 71, 119),
+    (92, 92, 154), (113, 113, 188), (133, 133, 222), (153, 153, 255),
+    (0, 0, 0), (4, 4, 44), (8, 8, 83), (12, 12, 119),
+    (15, 15, 154), (19, 19, 188), (22, 22, 222), (25, 25, 255),
+    (0, 0, 0), (34, 27, 44), (63, 50, 83), (90, 71, 119),
+    (117, 92, 154), (143, 113, 188), (169, 133, 222), (194, 153, 255),
+    (0, 0, 0), (20, 4, 44), (38, 8, 83), (55, 12, 119),
+    (71, 15, 154), (87, 19, 188), (102, 22, 222), (117, 25, 255),
+    (0, 0, 0), (41, 27, 44), (76, 50, 83), (109, 71, 119),
+    (142, 92, 154), (173, 113, 188), (204, 133, 222), (235, 153, 255),
+    (0, 0, 0), (36, 4, 44), (68, 8, 83), (98, 12, 119),
+    (126, 15, 154), (154, 19, 188), (182, 22, 222), (209, 25, 255),
+    (0, 0, 0), (44, 27, 41), (83, 50, 76), (119, 71, 109),
+    (154, 92, 142), (188, 113, 173), (222, 133, 204), (255, 153, 235),
+    (0, 0, 0), (44, 4, 36), (83, 8, 68), (119, 12, 98),
+    (154, 15, 126), (188, 19, 154), (222, 22, 182), (255, 25, 209),
+    (0, 0, 0), (44, 27, 34), (83, 50, 63), (119, 71, 90),
+    (154, 92, 117), (188, 113, 143), (222, 133, 169), (255, 153, 194),
+    (0, 0, 0), (44, 4, 20), (83, 8, 38), (119, 12, 55),
+    (154, 15, 71), (188, 19, 87), (222, 22, 102), (255, 25, 117),
+    (0, 0, 0), (17, 17, 17), (34, 34, 34), (51, 51, 51),
+    (68, 68, 68), (85, 85, 85), (102, 102, 102), (119, 119, 119),
+    (136, 136, 136), (153, 153, 153), (170, 170, 170), (187, 187, 187),
+    (204, 204, 204), (221, 221, 221), (238, 238, 238), (255, 255, 255),
+];
+
+/// The color the web frontend shows for a screen byte.
+pub fn color(index: u8) -> (u8, u8, u8) {
+    PALETTE[usize::from(index)]
+}
+
+/// Converts a frame from `Emulator::screen` to RGBA pixels, row by row.
+pub fn screen_to_rgba(screen: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], rgba: &mut [u8; RGBA_SCREEN_SIZE]) {
+    let pixels = screen.iter().flat_map(|row| row.iter());
+    for (&pixel, out) in pixels.zip(rgba.chunks_exact_mut(4)) {
+        let (r, g, b) = color(pixel);
+        out.copy_from_slice(&[r, g, b, 0xff]);
+    }
+}
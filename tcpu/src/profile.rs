@@ -0,0 +1,248 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use super::{Instruction, Tracer};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AddressProfile {
+    pub instructions: u64,
+    /// Instructions plus the cycles spent waiting after executing `wait`.
+    pub cycles: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    pub self_instructions: u64,
+    pub self_cycles: u64,
+    /// Including callees, recursive calls are only counted once.
+    pub total_instructions: u64,
+    pub total_cycles: u64,
+}
+
+// node of the call tree, one for every distinct stack of functions
+struct Node {
+    function: u16,
+    parent: usize,
+    children: HashMap<u16, usize>,
+    calls: u64,
+    instructions: u64,
+    cycles: u64,
+}
+
+impl Node {
+    fn new(function: u16, parent: usize) -> Self {
+        Node {
+            function,
+            parent,
+            children: HashMap::new(),
+            calls: 0,
+            instructions: 0,
+            cycles: 0,
+        }
+    }
+}
+
+struct State {
+    addresses: HashMap<u16, AddressProfile>,
+    nodes: Vec<Node>,
+    current: usize,
+    last_address: u16,
+    call_pending: bool,
+    names: HashMap<u16, String>,
+}
+
+/// A `Tracer` that keeps a shadow call stack by following `call` and `ret`
+/// and attributes every cycle to the instruction and the function that
+/// spent it. Functions are identified by the address of their first
+/// instruction, and code that isn't inside any call is attributed to the
+/// function at the address execution started from.
+pub struct Profiler {
+    state: RefCell<State>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const ROOT: usize = 0;
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            state: RefCell::new(State {
+                addresses: HashMap::new(),
+                nodes: Vec::new(),
+                current: ROOT,
+                last_address: 0,
+                call_pending: false,
+                names: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Names the function starting at `address` in reports,
+    /// e.g. with a label from the assembly source.
+    pub fn name_function(&self, address: u16, name: &str) {
+        self.state.borrow_mut().names.insert(address, name.to_owned());
+    }
+
+    /// Forgets collected counts, but keeps function names.
+    pub fn clear(&self) {
+        let state = &mut *self.state.borrow_mut();
+        state.addresses.clear();
+        state.nodes.clear();
+        state.current = ROOT;
+        state.call_pending = false;
+    }
+
+    pub fn addresses(&self) -> BTreeMap<u16, AddressProfile> {
+        self.state.borrow().addresses.iter().map(|(&a, &p)| (a, p)).collect()
+    }
+
+    pub fn functions(&self) -> BTreeMap<u16, FunctionProfile> {
+        let state = self.state.borrow();
+        let mut functions = BTreeMap::<u16, FunctionProfile>::new();
+        for (index, node) in state.nodes.iter().enumerate() {
+            let profile = functions.entry(node.function).or_default();
+            profile.calls += node.calls;
+            profile.self_instructions += node.instructions;
+            profile.self_cycles += node.cycles;
+            // add the node to totals of every distinct function on its stack
+            let mut seen = Vec::new();
+            let mut ancestor = index;
+            loop {
+                let function = state.nodes[ancestor].function;
+                if !seen.contains(&function) {
+                    seen.push(function);
+                    let profile = functions.entry(function).or_default();
+                    profile.total_instructions += node.instructions;
+                    profile.total_cycles += node.cycles;
+                }
+                if ancestor == ROOT {
+                    break;
+                }
+                ancestor = state.nodes[ancestor].parent;
+            }
+        }
+        functions
+    }
+
+    fn name(&self, function: u16) -> String {
+        match self.state.borrow().names.get(&function) {
+            Some(name) => name.clone(),
+            None => format!("0x{:04x}", function),
+        }
+    }
+
+    /// Writes per-function and per-address tables, hottest first.
+    pub fn write_report(&self, out: &mut impl Write) -> io::Result<()> {
+        let functions = self.functions();
+        let total = functions.values().map(|f| f.self_cycles).sum::<u64>().max(1);
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total as f64;
+
+        let mut sorted = functions.into_iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|&(address, f)| (std::cmp::Reverse(f.self_cycles), address));
+        writeln!(out, "  self%   self cycles  total%  total cycles      calls  function")?;
+        for (address, f) in sorted {
+            writeln!(
+                out,
+                "{:6.2}% {:13} {:6.2}% {:13} {:10}  {}",
+                percent(f.self_cycles),
+                f.self_cycles,
+                percent(f.total_cycles),
+                f.total_cycles,
+                f.calls,
+                self.name(address),
+            )?;
+        }
+
+        let mut addresses = self.addresses().into_iter().collect::<Vec<_>>();
+        addresses.sort_by_key(|&(address, a)| (std::cmp::Reverse(a.cycles), address));
+        writeln!(out)?;
+        writeln!(out, "      %        cycles  instructions  address")?;
+        for (address, a) in addresses {
+            writeln!(
+                out,
+                "{:6.2}% {:13} {:13}  0x{:04x}",
+                percent(a.cycles),
+                a.cycles,
+                a.instructions,
+                address,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes one `outer;inner cycles` line per call stack,
+    /// the format read by `flamegraph.pl` and similar tools.
+    pub fn write_folded(&self, out: &mut impl Write) -> io::Result<()> {
+        let state = self.state.borrow();
+        for (index, node) in state.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut stack = Vec::new();
+            let mut ancestor = index;
+            loop {
+                stack.push(self.name(state.nodes[ancestor].function));
+                if ancestor == ROOT {
+                    break;
+                }
+                ancestor = state.nodes[ancestor].parent;
+            }
+            stack.reverse();
+            writeln!(out, "{} {}", stack.join(";"), node.cycles)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tracer for Profiler {
+    fn on_instruction(&self, address: u16, instruction: Instruction) {
+        let state = &mut *self.state.borrow_mut();
+        if state.nodes.is_empty() {
+            state.nodes.push(Node::new(address, ROOT));
+        }
+        if state.call_pending {
+            state.call_pending = false;
+            let parent = state.current;
+            let next = state.nodes.len();
+            let child = *state.nodes[parent].children.entry(address).or_insert(next);
+            if child == next {
+                state.nodes.push(Node::new(address, parent));
+            }
+            state.current = child;
+            state.nodes[child].calls += 1;
+        }
+
+        let node = &mut state.nodes[state.current];
+        node.instructions += 1;
+        node.cycles += 1;
+        let profile = state.addresses.entry(address).or_default();
+        profile.instructions += 1;
+        profile.cycles += 1;
+        state.last_address = address;
+
+        match instruction {
+            Instruction::Call(_) => state.call_pending = true,
+            // returning from the outermost function leaves the stack as is
+            Instruction::Ret if state.current != ROOT => {
+                state.current = state.nodes[state.current].parent;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_idle(&self) {
+        let state = &mut *self.state.borrow_mut();
+        if state.nodes.is_empty() {
+            return;
+        }
+        state.nodes[state.current].cycles += 1;
+        let last_address = state.last_address;
+        state.addresses.entry(last_address).or_default().cycles += 1;
+    }
+}
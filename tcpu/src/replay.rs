@@ -0,0 +1,286 @@
+use core::convert::TryFrom;
+use core::fmt;
+use super::snapshot::{Reader, Writer};
+use super::{Device, DiskId, NoDevice, Emulator, NoopTracer, SnapshotError, Storage, Tracer, DISK_SIZE, MEMORY_SIZE};
+
+const MAGIC: &[u8; 8] = b"TCPUREPL";
+const VERSION: u16 = 3;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ReplayError::BadMagic => write!(f, "not a tcpu input log"),
+            ReplayError::UnsupportedVersion(v) => write!(f, "unsupported input log version {}", v),
+            ReplayError::Truncated => write!(f, "input log is truncated"),
+            ReplayError::Invalid => write!(f, "input log is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<SnapshotError> for ReplayError {
+    fn from(error: SnapshotError) -> Self {
+        match error {
+            SnapshotError::Truncated => ReplayError::Truncated,
+            _ => ReplayError::Invalid,
+        }
+    }
+}
+
+/// A single host interaction with the emulator.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Input {
+    KeyDown(u16),
+    KeyUp(u16),
+    /// Disk insertion, together with the disk contents at that moment.
+    InsertDisk {
+        id: DiskId,
+        read_only: bool,
+        data: Vec<u8>,
+    },
+    RemoveDisk(DiskId),
+    Reset,
+    ConsoleInput(u8),
+}
+
+/// Recorded inputs, each stamped with the value of `Emulator::cycles()`
+/// at the moment it was applied. Note that `reset` restarts the count.
+#[derive(Debug, Clone)]
+pub struct InputLog {
+    start: Vec<u8>,
+    inputs: Vec<(u64, Input)>,
+    end: u64,
+}
+
+impl InputLog {
+    pub fn inputs(&self) -> &[(u64, Input)] {
+        &self.inputs
+    }
+
+    /// Value of `Emulator::cycles()` when the recording ended.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer { out: Vec::new() };
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u64(self.start.len() as u64);
+        w.bytes(&self.start);
+        w.u64(self.inputs.len() as u64);
+        for (cycles, input) in &self.inputs {
+            w.u64(*cycles);
+            match input {
+                Input::KeyDown(key) => {
+                    w.u8(0);
+                    w.u16(*key);
+                }
+                Input::KeyUp(key) => {
+                    w.u8(1);
+                    w.u16(*key);
+                }
+                Input::InsertDisk { id, read_only, data } => {
+                    w.u8(2);
+                    w.u8(id.index() as u8);
+                    w.u8(u8::from(*read_only));
+                    w.bytes(data);
+                }
+                Input::RemoveDisk(id) => {
+                    w.u8(3);
+                    w.u8(id.index() as u8);
+                }
+                Input::Reset => w.u8(4),
+                Input::ConsoleInput(byte) => {
+                    w.u8(5);
+                    w.u8(*byte);
+                }
+            }
+        }
+        w.u64(self.end);
+        w.out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut r = Reader { input: bytes };
+        if r.bytes(MAGIC.len()).map_err(|_| ReplayError::BadMagic)? != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let start_len = r.usize()?;
+        let start = r.bytes(start_len)?.to_vec();
+        let count = r.usize()?;
+        let mut inputs = Vec::new();
+        for _ in 0..count {
+            let cycles = r.u64()?;
+            let input = match r.u8()? {
+                0 => Input::KeyDown(r.u16()?),
+                1 => Input::KeyUp(r.u16()?),
+                2 => {
+                    let id = DiskId::try_from(r.u8()?).map_err(|_| ReplayError::Invalid)?;
+                    let read_only = r.bool()?;
+                    let data = r.bytes(DISK_SIZE)?.to_vec();
+                    Input::InsertDisk { id, read_only, data }
+                }
+                3 => Input::RemoveDisk(DiskId::try_from(r.u8()?).map_err(|_| ReplayError::Invalid)?),
+                4 => Input::Reset,
+                5 => Input::ConsoleInput(r.u8()?),
+                _ => return Err(ReplayError::Invalid),
+            };
+            inputs.push((cycles, input));
+        }
+        let end = r.u64()?;
+        if !r.input.is_empty() {
+            return Err(ReplayError::Invalid);
+        }
+        Ok(InputLog { start, inputs, end })
+    }
+
+    /// Restores the state the recording started from and applies every
+    /// recorded input at its original cycle, then runs to the cycle the
+    /// recording ended on.
+    pub fn replay<SM, SD, T, D>(&self, emulator: &mut Emulator<SM, SD, T, D>) -> Result<(), ReplayError>
+    where
+        SM: Storage<[u8; MEMORY_SIZE]>,
+        SD: Storage<[u8; DISK_SIZE]>,
+        T: Tracer,
+        D: Device,
+    {
+        emulator.restore(&self.start)?;
+        for (cycles, input) in &self.inputs {
+            if *cycles < emulator.cycles() {
+                return Err(ReplayError::Invalid);
+            }
+            emulator.run(cycles - emulator.cycles());
+            // the log may come from a machine with more drives
+            if let Input::InsertDisk { id, .. } | Input::RemoveDisk(id) = input {
+                if id.index() >= emulator.disk_drives() {
+                    return Err(ReplayError::Invalid);
+                }
+            }
+            match input {
+                Input::KeyDown(key) => emulator.key_down(*key),
+                Input::KeyUp(key) => emulator.key_up(*key),
+                Input::InsertDisk { id, read_only, data } => {
+                    emulator.disk_slot(*id).write(0, data);
+                    if *read_only {
+                        emulator.insert_read_only_disk(*id);
+                    } else {
+                        emulator.insert_disk(*id);
+                    }
+                }
+                Input::RemoveDisk(id) => emulator.remove_disk(*id),
+                Input::Reset => emulator.reset(),
+                Input::ConsoleInput(byte) => emulator.console_input(*byte),
+            }
+        }
+        if self.end < emulator.cycles() {
+            return Err(ReplayError::Invalid);
+        }
+        emulator.run(self.end - emulator.cycles());
+        Ok(())
+    }
+}
+
+/// Wraps an emulator and records every host interaction made through it.
+pub struct Recorder<SM, SD, T = NoopTracer, D = NoDevice> {
+    emulator: Emulator<SM, SD, T, D>,
+    log: InputLog,
+}
+
+impl<SM, SD, T, D> Recorder<SM, SD, T, D>
+where
+    SM: Storage<[u8; MEMORY_SIZE]>,
+    SD: Storage<[u8; DISK_SIZE]>,
+    T: Tracer,
+    D: Device,
+{
+    pub fn new(emulator: Emulator<SM, SD, T, D>) -> Self {
+        let log = InputLog {
+            start: emulator.snapshot(),
+            inputs: Vec::new(),
+            end: emulator.cycles(),
+        };
+        Recorder { emulator, log }
+    }
+
+    pub fn emulator(&self) -> &Emulator<SM, SD, T, D> {
+        &self.emulator
+    }
+
+    /// Changes made directly through the returned emulator are not recorded.
+    pub fn emulator_mut(&mut self) -> &mut Emulator<SM, SD, T, D> {
+        &mut self.emulator
+    }
+
+    pub fn log(&self) -> &InputLog {
+        &self.log
+    }
+
+    pub fn into_parts(self) -> (Emulator<SM, SD, T, D>, InputLog) {
+        (self.emulator, self.log)
+    }
+
+    fn record(&mut self, input: Input) {
+        self.log.inputs.push((self.emulator.cycles(), input));
+    }
+
+    pub fn run(&mut self, cycles: u64) {
+        self.emulator.run(cycles);
+        self.log.end = self.emulator.cycles();
+    }
+
+    pub fn key_down(&mut self, key: u16) {
+        self.record(Input::KeyDown(key));
+        self.emulator.key_down(key);
+    }
+
+    pub fn key_up(&mut self, key: u16) {
+        self.record(Input::KeyUp(key));
+        self.emulator.key_up(key);
+    }
+
+    fn record_insert(&mut self, id: DiskId, read_only: bool) {
+        let mut data = vec![0; DISK_SIZE];
+        self.emulator.disk_slot(id).read(0, &mut data);
+        self.record(Input::InsertDisk { id, read_only, data });
+    }
+
+    pub fn insert_disk(&mut self, id: DiskId) {
+        self.record_insert(id, false);
+        self.emulator.insert_disk(id);
+    }
+
+    pub fn insert_read_only_disk(&mut self, id: DiskId) {
+        self.record_insert(id, true);
+        self.emulator.insert_read_only_disk(id);
+    }
+
+    pub fn remove_disk(&mut self, id: DiskId) {
+        self.record(Input::RemoveDisk(id));
+        self.emulator.remove_disk(id);
+    }
+
+    pub fn reset(&mut self) {
+        self.record(Input::Reset);
+        self.emulator.reset();
+        self.log.end = self.emulator.cycles();
+    }
+
+    pub fn console_input(&mut self, byte: u8) {
+        self.record(Input::ConsoleInput(byte));
+        self.emulator.console_input(byte);
+    }
+}
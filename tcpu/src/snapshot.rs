@@ -0,0 +1,395 @@
+use core::convert::TryInto;
+use core::fmt;
+use super::sound::SoundState;
+use super::timer::MAX_INTERVAL;
+use super::{
+    AUDIO_BUFFER_SIZE, CYCLES_PER_SAMPLE, CpuState, Device, DiskOp, Emulator, Event, MachineConfig, OverflowPolicy, Registers, SlotDisk, Storage,
+    CONSOLE_BUFFER_SIZE, DISK_BLOCK_SIZE, DISK_SIZE, MAX_EVENT_QUEUE_CAPACITY, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+const MAGIC: &[u8; 8] = b"TCPUSNAP";
+const VERSION: u16 = 8;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SnapshotError::BadMagic => write!(f, "not a tcpu snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Invalid => write!(f, "snapshot is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+pub(crate) struct Writer {
+    pub(crate) out: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.out.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.input.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, SnapshotError> {
+        self.u64()?.try_into().map_err(|_| SnapshotError::Invalid)
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Invalid),
+        }
+    }
+}
+
+struct DiskState<'a> {
+    disk: SlotDisk,
+    data: Option<&'a [u8]>,
+}
+
+fn write_disk<S: Storage<[u8; DISK_SIZE]>>(w: &mut Writer, disk: &SlotDisk, data: &S) {
+    match disk {
+        SlotDisk::Missing => w.u8(0),
+        SlotDisk::Present { modified, idle_time, read_only, running_op } => {
+            w.u8(1);
+            w.u8(u8::from(*modified));
+            w.u64(*idle_time);
+            w.u8(u8::from(*read_only));
+            match running_op {
+                None => w.u8(0),
+                Some(DiskOp::Reading { disk_ptr, memory_ptr, remaining, delay }) => {
+                    w.u8(1);
+                    w.u64(*disk_ptr as u64);
+                    w.u64(*memory_ptr as u64);
+                    w.u64(*remaining as u64);
+                    w.u64(*delay);
+                }
+                Some(DiskOp::Writing { disk_ptr, memory_ptr, remaining, delay }) => {
+                    w.u8(2);
+                    w.u64(*disk_ptr as u64);
+                    w.u64(*memory_ptr as u64);
+                    w.u64(*remaining as u64);
+                    w.u64(*delay);
+                }
+            }
+            let mut chunk = [0; DISK_BLOCK_SIZE * 256];
+            for offset in (0..DISK_SIZE).step_by(chunk.len()) {
+                data.read(offset, &mut chunk);
+                w.bytes(&chunk);
+            }
+        }
+    }
+}
+
+fn read_disk<'a>(r: &mut Reader<'a>) -> Result<DiskState<'a>, SnapshotError> {
+    if !r.bool()? {
+        return Ok(DiskState {
+            disk: SlotDisk::Missing,
+            data: None,
+        });
+    }
+    let modified = r.bool()?;
+    let idle_time = r.u64()?;
+    let read_only = r.bool()?;
+    let running_op = match r.u8()? {
+        0 => None,
+        kind @ 1 ..= 2 => {
+            let disk_ptr = r.usize()?;
+            let memory_ptr = r.usize()?;
+            let remaining = r.usize()?;
+            let delay = r.u64()?;
+            if remaining == 0 || delay == 0 {
+                return Err(SnapshotError::Invalid);
+            }
+            Some(if kind == 1 {
+                DiskOp::Reading { disk_ptr, memory_ptr, remaining, delay }
+            } else {
+                DiskOp::Writing { disk_ptr, memory_ptr, remaining, delay }
+            })
+        }
+        _ => return Err(SnapshotError::Invalid),
+    };
+    let data = r.bytes(DISK_SIZE)?;
+    Ok(DiskState {
+        disk: SlotDisk::Present { modified, idle_time, read_only, running_op },
+        data: Some(data),
+    })
+}
+
+impl<SM, SD, T, D> Emulator<SM, SD, T, D>
+where
+    SM: Storage<[u8; MEMORY_SIZE]>,
+    SD: Storage<[u8; DISK_SIZE]>,
+    D: Device,
+{
+    /// Serializes the whole machine state, including the contents of
+    /// inserted disks, so that it can later be resumed with `restore`.
+    /// The state of devices added with `with_devices` isn't included.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut w = Writer { out: Vec::new() };
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+
+        let config = &self.config;
+        w.u64(config.clock_rate);
+        w.u64(config.screen_refresh_time);
+        w.u64(config.cycles_per_byte);
+        w.u64(config.disk_op_size as u64);
+
+        let r = &self.registers;
+        for &value in &[r.a, r.b, r.c, r.d, r.i, r.j, r.p, r.s, self.instruction_pointer] {
+            w.u16(value);
+        }
+        w.u8(match self.state {
+            CpuState::Running => 0,
+            CpuState::Waiting => 1,
+            CpuState::Halted => 2,
+        });
+        w.u8(u8::from(self.instruction_pending));
+        w.u64(self.cycles);
+        w.u64(self.framebuffer.time_to_refresh);
+        let ((timer, (console, sound)), _) = &self.bus;
+        w.u64(timer.remaining);
+        w.u16(console.len as u16);
+        w.bytes(console.output());
+        for &phase in &sound.state.phases {
+            w.u64(u64::from(phase));
+        }
+        w.u16(sound.state.noise);
+        w.u64(sound.state.time_to_sample);
+        w.u16(sound.state.len as u16);
+        for &sample in sound.samples() {
+            w.u16(sample as u16);
+        }
+
+        let queue = &self.event_queue;
+        w.u16(queue.capacity as u16);
+        w.u8(match queue.policy {
+            OverflowPolicy::DropOldest => 0,
+            OverflowPolicy::DropNewest => 1,
+            OverflowPolicy::CoalesceScreenRefresh => 2,
+        });
+        w.u64(queue.lost);
+        w.u16(queue.unreported);
+        w.u16(queue.len as u16);
+        for event in queue.iter() {
+            w.u16(event.id);
+            w.u16(event.arg);
+        }
+
+        for row in &self.framebuffer.screen {
+            w.bytes(row);
+        }
+        w.bytes(self.memory.as_ref());
+
+        w.u8(self.disk_drives() as u8);
+        for slot in self.disk_slots.iter().flatten() {
+            write_disk(&mut w, &slot.disk, &slot.data);
+        }
+
+        w.out
+    }
+
+    /// Replaces the machine state with one previously produced by `snapshot`.
+    /// The emulator is left untouched if the snapshot cannot be decoded.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        let mut r = Reader { input: snapshot };
+        if r.bytes(MAGIC.len()).map_err(|_| SnapshotError::BadMagic)? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut config = MachineConfig {
+            clock_rate: r.u64()?,
+            screen_refresh_time: r.u64()?,
+            cycles_per_byte: r.u64()?,
+            disk_op_size: r.usize()?,
+            ..self.config
+        };
+
+        let registers = Registers {
+            a: r.u16()?,
+            b: r.u16()?,
+            c: r.u16()?,
+            d: r.u16()?,
+            i: r.u16()?,
+            j: r.u16()?,
+            p: r.u16()?,
+            s: r.u16()?,
+        };
+        let instruction_pointer = r.u16()?;
+        let state = match r.u8()? {
+            0 => CpuState::Running,
+            1 => CpuState::Waiting,
+            2 => CpuState::Halted,
+            _ => return Err(SnapshotError::Invalid),
+        };
+        let instruction_pending = r.bool()?;
+        let cycles = r.u64()?;
+        let time_to_refresh = r.u64()?;
+        let timer_remaining = r.u64()?;
+        if time_to_refresh > config.screen_refresh_time || timer_remaining > MAX_INTERVAL {
+            return Err(SnapshotError::Invalid);
+        }
+        let console_len = usize::from(r.u16()?);
+        if console_len > CONSOLE_BUFFER_SIZE {
+            return Err(SnapshotError::Invalid);
+        }
+        let console_output = r.bytes(console_len)?;
+        let mut sound = SoundState::new();
+        for phase in &mut sound.phases {
+            *phase = r.u64()?.try_into().map_err(|_| SnapshotError::Invalid)?;
+        }
+        sound.noise = r.u16()?;
+        sound.time_to_sample = r.u64()?;
+        sound.len = usize::from(r.u16()?);
+        if sound.len > AUDIO_BUFFER_SIZE || sound.time_to_sample == 0 || sound.time_to_sample > CYCLES_PER_SAMPLE {
+            return Err(SnapshotError::Invalid);
+        }
+        let samples = r.bytes(sound.len * 2)?;
+
+        let mut event_queue = self.event_queue.clone();
+        event_queue.capacity = usize::from(r.u16()?);
+        event_queue.policy = match r.u8()? {
+            0 => OverflowPolicy::DropOldest,
+            1 => OverflowPolicy::DropNewest,
+            2 => OverflowPolicy::CoalesceScreenRefresh,
+            _ => return Err(SnapshotError::Invalid),
+        };
+        event_queue.lost = r.u64()?;
+        event_queue.unreported = r.u16()?;
+        config.event_queue_capacity = event_queue.capacity;
+        config.overflow_policy = event_queue.policy;
+        event_queue.head = 0;
+        event_queue.len = usize::from(r.u16()?);
+        if event_queue.capacity == 0
+            || event_queue.capacity > MAX_EVENT_QUEUE_CAPACITY
+            || event_queue.len > event_queue.capacity
+            || !config.is_valid()
+        {
+            return Err(SnapshotError::Invalid);
+        }
+        for item in &mut event_queue.items[..event_queue.len] {
+            let id = r.u16()?;
+            let arg = r.u16()?;
+            *item = Event { id, arg };
+        }
+
+        let screen = r.bytes(SCREEN_WIDTH * SCREEN_HEIGHT)?;
+        let memory = r.bytes(MEMORY_SIZE)?;
+
+        let drives = self.disk_drives();
+        if usize::from(r.u8()?) != drives {
+            return Err(SnapshotError::Invalid);
+        }
+        let disks = (0..drives)
+            .map(|_| read_disk(&mut r))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !r.input.is_empty() {
+            return Err(SnapshotError::Invalid);
+        }
+
+        self.config = config;
+        self.framebuffer.refresh_time = config.screen_refresh_time;
+        self.registers = registers;
+        self.instruction_pointer = instruction_pointer;
+        self.state = state;
+        self.instruction_pending = instruction_pending;
+        self.cycles = cycles;
+        self.framebuffer.time_to_refresh = time_to_refresh;
+        let ((timer, (console, sound_device)), _) = &mut self.bus;
+        timer.remaining = timer_remaining;
+        console.output[..console_len].copy_from_slice(console_output);
+        console.len = console_len;
+        for (sample, bytes) in sound_device.samples.iter_mut().zip(samples.chunks(2)) {
+            *sample = i16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        sound_device.state = sound;
+        self.event_queue = event_queue;
+        self.clear_history();
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.clear();
+        }
+        for (row, data) in self.framebuffer.screen.iter_mut().zip(screen.chunks(SCREEN_WIDTH)) {
+            row.copy_from_slice(data);
+        }
+        self.memory.as_mut().copy_from_slice(memory);
+        for (slot, state) in self.disk_slots.iter_mut().flatten().zip(disks) {
+            slot.disk = state.disk;
+            // only blocks that differ are written, so that storages
+            // which track writes see just the changes
+            if let Some(data) = state.data {
+                let mut current = [0; DISK_BLOCK_SIZE];
+                for (offset, block) in (0..DISK_SIZE).step_by(DISK_BLOCK_SIZE).zip(data.chunks(DISK_BLOCK_SIZE)) {
+                    slot.data.read(offset, &mut current);
+                    if current != block {
+                        slot.data.write(offset, block);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Device, Events, MEMORY_SIZE};
+
+pub(crate) const SOUND_POSITION: u16 = 0xcc10;
+const CHANNELS: usize = 4;
+const NOISE_CHANNEL: usize = 3;
+const CHANNEL_SIZE: u16 = 4;
+const SOUND_END: u16 = SOUND_POSITION + CHANNELS as u16 * CHANNEL_SIZE - 1;
+
+/// A sample is produced whenever `Emulator::cycles()` is a multiple of this.
+pub const CYCLES_PER_SAMPLE: u64 = 32;
+pub const AUDIO_BUFFER_SIZE: usize = 8192;
+
+// units of the period register
+const CYCLES_PER_PERIOD_UNIT: u32 = 16;
+// channel output at volume 1, so that all channels at full volume can't clip
+const VOLUME_STEP: i16 = 512;
+
+/// Three square wave channels and one noise channel, see the sound
+/// section in `arch.txt`. Registers are kept in RAM and read back
+/// every time a sample is produced.
+#[derive(Clone)]
+pub(crate) struct Sound {
+    pub(crate) state: SoundState,
+    pub(crate) samples: [i16; AUDIO_BUFFER_SIZE],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SoundState {
+    // cycles elapsed in the current period of each channel
+    pub(crate) phases: [u32; CHANNELS],
+    pub(crate) noise: u16,
+    pub(crate) time_to_sample: u64,
+    pub(crate) len: usize,
+}
+
+impl SoundState {
+    pub(crate) const fn new() -> Self {
+        SoundState {
+            phases: [0; CHANNELS],
+            noise: 1,
+            time_to_sample: CYCLES_PER_SAMPLE,
+            len: 0,
+        }
+    }
+}
+
+impl Sound {
+    pub(crate) const fn new() -> Self {
+        Sound {
+            state: SoundState::new(),
+            samples: [0; AUDIO_BUFFER_SIZE],
+        }
+    }
+
+    pub(crate) fn samples(&self) -> &[i16] {
+        &self.samples[..self.state.len]
+    }
+
+    fn sample(&mut self, memory: &[u8; MEMORY_SIZE]) -> i16 {
+        let mut sample = 0;
+        for channel in 0..CHANNELS {
+            let base = usize::from(SOUND_POSITION) + channel * usize::from(CHANNEL_SIZE);
+            let period = u32::from(u16::from_le_bytes([memory[base], memory[base + 1]]));
+            let volume = i16::from(memory[base + 2] & 0xf);
+            let duty = u32::from(memory[base + 3]);
+            if period == 0 {
+                continue;
+            }
+            let period = period * CYCLES_PER_PERIOD_UNIT;
+            let phase = &mut self.state.phases[channel];
+            *phase += CYCLES_PER_SAMPLE as u32;
+            let high = if channel == NOISE_CHANNEL {
+                while *phase >= period {
+                    *phase -= period;
+                    // 15 bit lfsr with taps 0 and 1
+                    let noise = self.state.noise;
+                    let bit = (noise ^ (noise >> 1)) & 1;
+                    self.state.noise = (noise >> 1) | (bit << 14);
+                }
+                self.state.noise & 1 == 0
+            } else {
+                *phase %= period;
+                *phase * 256 < duty * period
+            };
+            sample += if high { volume } else { -volume };
+        }
+        sample * VOLUME_STEP
+    }
+}
+
+impl Device for Sound {
+    fn contains(&self, address: u16) -> bool {
+        (SOUND_POSITION..=SOUND_END).contains(&address)
+    }
+
+    fn tick(&mut self, events: &mut Events<'_>) {
+        self.skip(events.memory(), 1);
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        u64::MAX
+    }
+
+    // the registers can't change while the emulator skips cycles,
+    // so all samples in between can be produced at once
+    fn skip(&mut self, memory: &[u8; MEMORY_SIZE], mut cycles: u64) {
+        while cycles >= self.state.time_to_sample {
+            cycles -= self.state.time_to_sample;
+            self.state.time_to_sample = CYCLES_PER_SAMPLE;
+            let sample = self.sample(memory);
+            // samples that don't fit are dropped until the host clears the buffer
+            if self.state.len < AUDIO_BUFFER_SIZE {
+                self.samples[self.state.len] = sample;
+                self.state.len += 1;
+            }
+        }
+        self.state.time_to_sample -= cycles;
+    }
+
+    fn reset(&mut self) {
+        self.state = SoundState {
+            len: self.state.len,
+            ..SoundState::new()
+        };
+    }
+}
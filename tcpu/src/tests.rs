@@ -403,6 +403,379 @@ fn event() {
     assert_eq!(emulator.registers.a, 0);
 }
 
+#[test]
+fn write_to_read_only_disk() {
+    let mut emulator = emulator();
+    let code = [
+        0xf9, 0x88, // write1 0, 0
+        0x02, // wait
+        0x04, // halt
+    ];
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.insert_read_only_disk(DiskId::D1);
+    emulator.run(10);
+    assert!(!emulator.is_running());
+    assert_eq!(emulator.registers.a, 5);
+    assert_eq!(emulator.registers.b, 3);
+    let disk = emulator.disk(DiskId::D1).unwrap();
+    assert!(disk.read_only);
+    assert!(!disk.modified);
+}
+
+#[test]
+fn run_until_stops() {
+    let mut emulator = emulator();
+    let code = [
+        0x80, 0x0d, 0x05, // mov a, 5
+        0x81, 0x09, // add a, 1
+        0x98, 0x0e, 0x00, 0x20, // store a, 0x2000
+        0x02, // wait
+        0x04, // halt
+    ];
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.add_breakpoint(3);
+    emulator.add_watchpoint(0x1fff..=0x2000, Watch::Write);
+    assert_eq!(emulator.run_until(100), StopReason::Breakpoint(3));
+    assert_eq!(emulator.registers.a, 5);
+    assert_eq!(emulator.cycles(), 2);
+    assert_eq!(
+        emulator.run_until(100),
+        StopReason::Watchpoint { addr: 0x2000, value: 6, wide: false },
+    );
+    assert_eq!(emulator.cycles(), 3);
+    assert_eq!(emulator.run_until(100), StopReason::Waiting);
+    assert_eq!(emulator.run_until(100), StopReason::BudgetExhausted);
+    emulator.key_down(1);
+    assert_eq!(emulator.run_until(100), StopReason::Halted);
+    assert_eq!(emulator.cycles(), 105);
+}
+
+#[test]
+fn disassemble_code() {
+    let code = [
+        0x80, 0x0d, 0x05, // mov a, 5
+        0x9a, 0x0e, 0x34, 0x12, 0x00, 0x20, // store a, 0x2000 + 0x1234
+        0x9e, 0xee, 0x00, 0x01, 0x11, 0x11, 0x00, 0x20, // storew 0x1111, 0x2000 + 0x100
+        0xf1, 0xe8, 0x00, // read1 (cut off)
+    ];
+    assert_eq!(
+        decode(&code, 3),
+        (Instruction::Store(Operand::Register(Register::A), Address {
+            operand: Operand::Word(0x2000),
+            offset: 0x1234,
+        }), 6),
+    );
+    assert_eq!(decode(&code, 0xffff), (Instruction::Nop, 1));
+
+    let listing = disassemble(&code, 0x100).map(|i| i.to_string()).collect::<Vec<_>>();
+    assert_eq!(listing, [
+        "0100: 80 0d 05                 mov A, 5",
+        "0103: 9a 0e 34 12 00 20        store A, 8192 + 4660",
+        "0109: 9e ee 00 01 11 11 00 20  storew 4369, 8192 + 256",
+        "0111: f1 e8 00                 read1 0, 0",
+    ]);
+    // offsets into a disk image go past the address space
+    let listing = disassemble(&code, 0xfffd).map(|i| i.address).collect::<Vec<_>>();
+    assert_eq!(listing, [0xfffd, 0x10000, 0x10006, 0x1000e]);
+
+    let mut emulator = emulator();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.cycle();
+    assert_eq!(emulator.instruction_pointer(), 3);
+}
+
+#[test]
+fn profile_calls() {
+    let code = [
+        0x80, 0x7e, 0x00, 0xf0, // mov s, 0xf000
+        0x6d, 0x0a, // call 10
+        0x6d, 0x0a, // call 10
+        0x02, // wait
+        0x04, // halt
+        0x81, 0x09, // add a, 1
+        0x01, // ret
+    ];
+    let mut emulator = Emulator::<Memory, DiskMemory, _>::with_tracer(
+        Default::default(),
+        Default::default(),
+        Profiler::new(),
+    );
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.run(100);
+    emulator.key_down(1);
+    emulator.run(10);
+
+    let profiler = emulator.tracer();
+    let functions = profiler.functions();
+    assert_eq!(functions[&0], FunctionProfile {
+        calls: 0,
+        self_instructions: 5,
+        self_cycles: 97,
+        total_instructions: 9,
+        total_cycles: 101,
+    });
+    assert_eq!(functions[&10], FunctionProfile {
+        calls: 2,
+        self_instructions: 4,
+        self_cycles: 4,
+        total_instructions: 4,
+        total_cycles: 4,
+    });
+    assert_eq!(profiler.addresses()[&8], AddressProfile { instructions: 1, cycles: 93 });
+
+    profiler.name_function(10, "inc");
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded).unwrap();
+    assert_eq!(String::from_utf8(folded).unwrap(), "0x0000 97\n0x0000;inc 4\n");
+}
+
+#[test]
+fn custom_device() {
+    struct Counter {
+        value: u8,
+        stored: Option<u8>,
+    }
+
+    impl Device for Counter {
+        fn contains(&self, address: u16) -> bool {
+            address == 0xd000
+        }
+
+        fn load(&mut self, _memory: &[u8; MEMORY_SIZE], _address: u16) -> u8 {
+            self.value += 1;
+            self.value
+        }
+
+        fn store(&mut self, _memory: &mut [u8; MEMORY_SIZE], _address: u16, value: u8) {
+            self.stored = Some(value);
+        }
+
+        fn tick(&mut self, events: &mut Events<'_>) {
+            if let Some(value) = self.stored.take() {
+                events.push(Event { id: 100, arg: u16::from(value) });
+            }
+        }
+    }
+
+    let code = [
+        0x90, 0x0e, 0x00, 0xd0, // load a, 0xd000
+        0x90, 0x1e, 0x00, 0xd0, // load b, 0xd000
+        0x98, 0x1e, 0x00, 0xd0, // store b, 0xd000
+        0x02, // wait
+        0x04, // halt
+    ];
+    let counter = Counter { value: 0, stored: None };
+    let mut emulator = Emulator::<Memory, DiskMemory, _, _>::with_devices(
+        Default::default(),
+        Default::default(),
+        NoopTracer,
+        counter,
+    );
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.run(10);
+    assert!(!emulator.is_running());
+    assert_eq!((emulator.registers().a, emulator.registers().b), (100, 2));
+    assert_eq!(emulator.memory()[0xd000], 0);
+    assert_eq!(emulator.devices().value, 2);
+}
+
+#[test]
+fn trace_disk_ops() {
+    #[derive(Debug, PartialEq, Eq)]
+    enum DiskTrace {
+        Start(DiskId, DiskOpKind, u16, usize),
+        Finish(DiskId, DiskResult),
+    }
+
+    #[derive(Default)]
+    struct DiskTracer {
+        ops: std::cell::RefCell<Vec<DiskTrace>>,
+        stores: std::cell::RefCell<Vec<(u16, u8)>>,
+    }
+
+    impl Tracer for DiskTracer {
+        fn on_disk_op_start(&self, disk: DiskId, kind: DiskOpKind, memory_ptr: u16, disk_ptr: usize) {
+            self.ops.borrow_mut().push(DiskTrace::Start(disk, kind, memory_ptr, disk_ptr));
+        }
+
+        fn on_disk_op_finish(&self, disk: DiskId, result: DiskResult) {
+            self.ops.borrow_mut().push(DiskTrace::Finish(disk, result));
+        }
+
+        fn on_dma_store(&self, disk: DiskId, address: u16, value: u8) {
+            assert_eq!(disk, DiskId::D0);
+            self.stores.borrow_mut().push((address, value));
+        }
+    }
+
+    let code = [
+        0xf0, 0xe8, 0x00, 0x20, // read0 0x2000, 0
+        0x02, // wait
+        0xf1, 0xe8, 0x00, 0x30, // read1 0x3000, 0
+        0x02, // wait
+        0xf8, 0xea, 0x00, 0x20, // write0 0x2000, 2
+        0x02, // wait
+        0x04, // halt
+    ];
+    // fast disks finish before the first screen refresh can end a wait
+    let mut emulator = Emulator::<Memory, DiskMemory, _>::with_config(
+        MachineConfig::fast_disk(),
+        Default::default(),
+        [Default::default(), Default::default()],
+        DiskTracer::default(),
+        NoDevice,
+    );
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    for (i, byte) in emulator.disk_slot(DiskId::D0).as_mut().iter_mut().enumerate() {
+        *byte = (i * 3) as u8;
+    }
+    emulator.insert_disk(DiskId::D0);
+    emulator.run(10_000);
+    assert!(!emulator.is_running());
+
+    let tracer = emulator.tracer();
+    assert_eq!(*tracer.ops.borrow(), [
+        DiskTrace::Start(DiskId::D0, DiskOpKind::Read, 0x2000, 0),
+        DiskTrace::Finish(DiskId::D0, DiskResult::Ok),
+        DiskTrace::Finish(DiskId::D1, DiskResult::DiskNotPresent),
+        DiskTrace::Start(DiskId::D0, DiskOpKind::Write, 0x2000, 32),
+        DiskTrace::Finish(DiskId::D0, DiskResult::Ok),
+    ]);
+    let stores = tracer.stores.borrow();
+    assert_eq!(stores.len(), emulator.config().disk_op_size);
+    assert_eq!(stores[5], (0x2005, 15));
+}
+
+#[test]
+fn trace_lifecycle() {
+    #[derive(Default)]
+    struct LifecycleTracer {
+        log: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl LifecycleTracer {
+        fn push(&self, entry: String) {
+            self.log.borrow_mut().push(entry);
+        }
+    }
+
+    impl Tracer for LifecycleTracer {
+        fn on_event(&self, event: Event) {
+            self.push(format!("event {} {}", event.id, event.arg));
+        }
+
+        fn on_wait_start(&self) {
+            self.push("wait start".to_owned());
+        }
+
+        fn on_wait_end(&self) {
+            self.push("wait end".to_owned());
+        }
+
+        fn on_halt(&self) {
+            self.push("halt".to_owned());
+        }
+
+        fn on_reset(&self) {
+            self.push("reset".to_owned());
+        }
+
+        fn on_invalid_instruction(&self, address: u16) {
+            self.push(format!("invalid {}", address));
+        }
+    }
+
+    let code = [
+        0x03, // poll
+        0x03, // poll
+        0x05, // invalid
+        0x02, // wait
+        0x02, // wait
+    ];
+    let mut emulator = Emulator::<Memory, DiskMemory, _>::with_tracer(
+        Default::default(),
+        Default::default(),
+        LifecycleTracer::default(),
+    );
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.key_down(65);
+    emulator.run(10);
+    emulator.key_up(65);
+    emulator.run(10);
+    // reset while waiting
+    emulator.reset();
+    emulator.memory_mut()[0] = 0x04; // halt
+    emulator.run(10);
+    assert!(!emulator.is_running());
+    assert_eq!(*emulator.tracer().log.borrow(), [
+        "event 2 65",
+        "invalid 2",
+        "wait start",
+        "event 1 65",
+        "wait end",
+        "wait start",
+        "wait end",
+        "reset",
+        "halt",
+    ]);
+}
+
+#[test]
+fn timer() {
+    for &(control, fired) in &[(0x12, 4), (0x11, 1), (0x10, 0)] {
+        let code = [
+            0x9c, 0xde, 0x64, 0x00, 0xcc, // storew 100, 0xcc00
+            0x98, 0xde, control, 0x02, 0xcc, // store control, 0xcc02
+            0x02, // wait
+            0x81, 0x29, // add c, 1
+            0x5d, 0x0a, // jmp 10
+        ];
+        let mut emulator = emulator();
+        emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+        emulator.run(201);
+        assert_eq!(emulator.registers().c, 0);
+        emulator.run(799);
+        assert_eq!(emulator.registers().c, fired, "control {:#04x}", control);
+    }
+}
+
+#[test]
+fn console() {
+    let code = [
+        0x98, 0xde, b'h', 0x04, 0xcc, // store 'h', 0xcc04
+        0x98, 0xde, b'i', 0x04, 0xcc, // store 'i', 0xcc04
+        0x02, // wait
+        0x98, 0x1e, 0x04, 0xcc, // store b, 0xcc04
+        0x5d, 0x0a, // jmp 10
+    ];
+    let mut emulator = emulator();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.run(20);
+    assert_eq!(emulator.console_output(), b"hi");
+    emulator.clear_console_output();
+    emulator.console_input(b'!');
+    emulator.console_input(b'?');
+    emulator.run(20);
+    assert_eq!(emulator.console_output(), b"!?");
+}
+
+#[test]
+fn square_wave() {
+    let code = [
+        0x9c, 0xce, 0x10, 0xcc, // storew 4, 0xcc10
+        0x98, 0xde, 0x0f, 0x12, 0xcc, // store 15, 0xcc12
+        0x98, 0xde, 0x80, 0x13, 0xcc, // store 0x80, 0xcc13
+        0x04, // halt
+    ];
+    let mut emulator = emulator();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    let low = -15 * 512;
+    let high = 15 * 512;
+    assert_eq!(emulator.render_audio(256), &[low, high, low, high, low, high, low, high]);
+    assert_eq!(emulator.render_audio(100).len(), 3);
+    assert_eq!(emulator.render_audio(28).len(), 1);
+}
+
 #[test]
 fn event_queue() {
     let mut queue = EventQueue::new();
@@ -417,6 +790,591 @@ fn event_queue() {
     assert_eq!(queue.pop(), None);
 }
 
+#[test]
+fn event_queue_overflow() {
+    let mut queue = EventQueue::new();
+    queue.set_capacity(2);
+    for key in 0..3 {
+        queue.push(Event::key_down(key));
+    }
+    assert_eq!(queue.lost, 1);
+    assert_eq!(queue.pop(), Some(Event::events_lost(1)));
+    assert_eq!(queue.pop(), Some(Event::key_down(1)));
+    assert_eq!(queue.pop(), Some(Event::key_down(2)));
+    assert_eq!(queue.pop(), None);
+
+    queue.policy = OverflowPolicy::DropNewest;
+    for key in 0..3 {
+        queue.push(Event::key_down(key));
+    }
+    assert_eq!(queue.lost, 2);
+    assert_eq!(queue.pop(), Some(Event::events_lost(1)));
+    assert_eq!(queue.pop(), Some(Event::key_down(0)));
+    assert_eq!(queue.pop(), Some(Event::key_down(1)));
+    assert_eq!(queue.pop(), None);
+
+    queue.policy = OverflowPolicy::CoalesceScreenRefresh;
+    queue.push(Event::screen_refresh());
+    queue.push(Event::screen_refresh());
+    queue.push(Event::key_up(7));
+    assert_eq!(queue.lost, 2);
+    assert_eq!(queue.pop(), Some(Event::screen_refresh()));
+    assert_eq!(queue.pop(), Some(Event::key_up(7)));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn events_lost() {
+    let code = [
+        0x02, // wait
+        0x04, // halt
+    ];
+    let mut emulator = emulator();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.set_event_queue_capacity(4);
+    for key in 0..10 {
+        emulator.key_down(key);
+    }
+    assert_eq!(emulator.events_lost(), 6);
+    emulator.run(2);
+    assert_eq!(emulator.registers().a, 8);
+    assert_eq!(emulator.registers().b, 6);
+    assert!(!emulator.is_running());
+
+    emulator.reset();
+    assert_eq!(emulator.event_queue_capacity(), 4);
+    assert_eq!(emulator.events_lost(), 0);
+}
+
+#[test]
+fn machine_config() {
+    let code = [
+        0xf1, 0xe8, 0x00, 0x20, // read1 0x2000, 0
+        0x02, // wait
+        0x04, // halt
+    ];
+    let config = MachineConfig {
+        screen_refresh_time: 100_000,
+        event_queue_capacity: 8,
+        ..MachineConfig::fast_disk()
+    };
+    let mut emulator: Emulator<Memory, DiskMemory> =
+        Emulator::with_config(config, Default::default(), [Default::default(), Default::default()], NoopTracer, NoDevice);
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.disk_slot(DiskId::D1).as_mut()[0xfff] = 42;
+    emulator.insert_disk(DiskId::D1);
+    emulator.run(4100);
+    assert!(!emulator.is_running());
+    assert_eq!(emulator.registers().a, 5);
+    assert_eq!(emulator.memory()[0x2fff], 42);
+
+    let snapshot = emulator.snapshot();
+    let mut restored = self::emulator();
+    restored.restore(&snapshot).unwrap();
+    assert_eq!(restored.config(), config);
+}
+
+#[test]
+fn extra_disk_drives() {
+    let code = [
+        0xf2, 0xe8, 0x00, 0x20, // read2 0x2000, 0
+        0x02, // wait
+        0x80, 0x20, // mov c, a
+        0xfb, 0x88, // write3 0, 0
+        0x02, // wait
+        0x04, // halt
+    ];
+    assert_eq!(decode(&code, 0).0.to_string(), "read2 8192, 0");
+    let disks = [Default::default(), Default::default(), Default::default()];
+    let mut emulator: Emulator<Memory, DiskMemory> =
+        Emulator::with_config(MachineConfig::fast_disk(), Default::default(), disks, NoopTracer, NoDevice);
+    assert_eq!(emulator.disk_drives(), 3);
+    assert!(emulator.disk(DiskId::D3).is_none());
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.disk_slot(DiskId::D2).as_mut()[0xfff] = 42;
+    emulator.insert_disk(DiskId::D2);
+    emulator.run(5000);
+    assert!(!emulator.is_running());
+    assert_eq!(emulator.memory()[0x2fff], 42);
+    assert_eq!(emulator.registers().c, 9);
+    // disk 3 isn't connected
+    assert_eq!(emulator.registers().a, 10);
+    assert_eq!(emulator.registers().b, 1);
+
+    let snapshot = emulator.snapshot();
+    let mut restored = self::emulator();
+    assert_eq!(restored.restore(&snapshot), Err(SnapshotError::Invalid));
+}
+
+#[test]
+fn run_skips_idle_cycles() {
+    let code = [
+        0x9c, 0xde, 0xfa, 0x00, 0xcc, // storew 250, 0xcc00
+        0x98, 0xde, 0x12, 0x02, 0xcc, // store 0x12, 0xcc02
+        0x9c, 0xde, 0x07, 0x10, 0xcc, // storew 7, 0xcc10
+        0x98, 0xde, 0x09, 0x12, 0xcc, // store 9, 0xcc12
+        0x98, 0xde, 0x40, 0x13, 0xcc, // store 0x40, 0xcc13
+        0x9c, 0xbe, 0x1c, 0xcc, // storew 3, 0xcc1c
+        0x98, 0xde, 0x05, 0x1e, 0xcc, // store 5, 0xcc1e
+        0x02, // wait
+        0x9a, 0x04, 0x00, 0x10, // store a, i + 0x1000
+        0x81, 0x49, // add i, 1
+        0x84, 0x4d, 0xff, // and i, 0xff
+        0xf1, 0xe8, 0x00, 0x20, // read1 0x2000, 0
+        0x80, 0x2e, 0x2c, 0x01, // mov c, 300
+        0x82, 0x29, // sub c, 1
+        0xa1, 0x2e, 0x34, 0x00, // jnz c, 0x34
+        0x98, 0xde, 0x12, 0x02, 0xcc, // store 0x12, 0xcc02
+        0x5e, 0x22, 0x00, // jmp 0x22
+    ];
+    let mut skipping = emulator();
+    let mut stepping = emulator();
+    for emulator in &mut [&mut skipping, &mut stepping] {
+        emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+        for (i, byte) in emulator.disk_slot(DiskId::D1).as_mut().iter_mut().enumerate() {
+            *byte = (i * 3) as u8;
+        }
+        emulator.insert_disk(DiskId::D1);
+    }
+    for &cycles in &[1, 35, 1000, 77_777, 3, 150_000, 100_000] {
+        skipping.run(cycles);
+        for _ in 0..cycles {
+            stepping.cycle();
+        }
+        assert_eq!(skipping.cycles(), stepping.cycles());
+        assert!(skipping.snapshot() == stepping.snapshot());
+        skipping.key_down(cycles as u16);
+        stepping.key_down(cycles as u16);
+    }
+    assert_ne!(skipping.audio_samples().len(), 0);
+}
+
+#[test]
+fn instruction_cache() {
+    let code = [
+        0x81, 0x19, // add b, 1
+        0x98, 0xde, 0x1a, 0x01, 0x00, // store 0x1a, 1
+        0x5d, 0x00, // jmp 0
+    ];
+    let mut emulator = emulator();
+    emulator.enable_instruction_cache();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.run(4);
+    // the store turned `add b, 1` into `add b, 2`
+    assert_eq!(emulator.registers().b, 3);
+    emulator.memory_mut()[6] = 0xff;
+    emulator.run(6);
+    assert_eq!(emulator.registers().b, 7);
+    assert_eq!(emulator.memory()[0xff01], 0x1a);
+
+    // patching the last byte of the longest instruction
+    let code = [
+        0x9e, 0xee, 0x00, 0x01, 0x11, 0x11, 0x00, 0x20, // storew 0x1111, 0x2000 + 0x100
+        0x98, 0xde, 0x30, 0x07, 0x00, // store 0x30, 7
+        0x5d, 0x00, // jmp 0
+    ];
+    let mut emulator = self::emulator();
+    emulator.enable_instruction_cache();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.run(4);
+    assert_eq!(emulator.memory()[0x2100], 0x11);
+    assert_eq!(emulator.memory()[0x3100], 0x11);
+
+    // a device patching code while ticking
+    struct Patcher {
+        cycles: u64,
+    }
+
+    impl Device for Patcher {
+        fn contains(&self, _address: u16) -> bool {
+            false
+        }
+
+        fn tick(&mut self, events: &mut Events<'_>) {
+            self.cycles += 1;
+            if self.cycles == 3 {
+                events.store(1, 0x1a);
+            }
+        }
+    }
+
+    let code = [
+        0x81, 0x19, // add b, 1
+        0x5d, 0x00, // jmp 0
+    ];
+    for &cache in &[false, true] {
+        let mut emulator = Emulator::<Memory, DiskMemory, _, _>::with_devices(
+            Default::default(),
+            Default::default(),
+            NoopTracer,
+            Patcher { cycles: 0 },
+        );
+        if cache {
+            emulator.enable_instruction_cache();
+        }
+        emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+        emulator.run(4);
+        // the device turned `add b, 1` into `add b, 2` before the second add
+        assert_eq!(emulator.registers().b, 3);
+    }
+
+    for seed in 0..32 {
+        let mut cached = random_emulator(seed);
+        let mut uncached = random_emulator(seed);
+        cached.enable_instruction_cache();
+        for _ in 0..10 {
+            cached.run(1000);
+            uncached.run(1000);
+            assert!(cached.snapshot() == uncached.snapshot(), "seed {}", seed);
+        }
+    }
+}
+
+#[test]
+fn palette() {
+    // getColor from web/index.ts
+    fn web_color(index: u8) -> (u8, u8, u8) {
+        fn to_rgb(h: f64, s: f64, v: f64) -> (u8, u8, u8) {
+            let c = v * s;
+            let x = c * (1.0 - (h / 60.0 % 2.0 - 1.0).abs());
+            let m = v - c;
+            let (r, g, b) = match (h / 60.0) as u32 {
+                0 => (c, x, 0.0),
+                1 => (x, c, 0.0),
+                2 => (0.0, c, x),
+                3 => (0.0, x, c),
+                4 => (x, 0.0, c),
+                _ => (c, 0.0, x),
+            };
+            let channel = |value: f64| ((value + m) * 255.0).round() as u8;
+            (channel(r), channel(g), channel(b))
+        }
+        let h = index >> 4;
+        let s = (index >> 3) & 1;
+        let v = index & 7;
+        if h == 15 {
+            return to_rgb(0.0, 0.0, f64::from(index % 16) / 15.0);
+        }
+        to_rgb(f64::from(h) / 15.0 * 360.0, f64::from(s) * 0.5 + 0.4, (f64::from(v) / 7.0).powf(0.9))
+    }
+    for index in 0..=255 {
+        assert_eq!(palette::color(index), web_color(index), "color {}", index);
+    }
+
+    let mut screen = [[0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+    screen[0][1] = 0x0f;
+    screen[SCREEN_HEIGHT - 1][SCREEN_WIDTH - 1] = 0xff;
+    let mut rgba = [0; palette::RGBA_SCREEN_SIZE];
+    palette::screen_to_rgba(&screen, &mut rgba);
+    assert_eq!(rgba[..8], [0, 0, 0, 0xff, 255, 25, 25, 0xff]);
+    assert_eq!(rgba[palette::RGBA_SCREEN_SIZE - 4..], [255, 255, 255, 0xff]);
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn screen_capture() {
+    let code = [
+        0x02, // wait
+        0x81, 0x29, // add c, 1
+        0x98, 0x2e, 0x00, 0xc0, // store c, 0xc000
+        0x5d, 0x00, // jmp 0
+    ];
+    let mut emulator = Emulator::<Memory, DiskMemory, _>::with_tracer(
+        Default::default(),
+        Default::default(),
+        ScreenCapture::new(2..5),
+    );
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    emulator.run(emulator.config().screen_refresh_time * 8 + 100);
+    assert_eq!(emulator.tracer().refreshes(), 8);
+    let frames = emulator.tracer().take_frames();
+    assert_eq!(frames.iter().map(|frame| frame[0][0]).collect::<Vec<_>>(), [2, 3, 4]);
+    assert!(emulator.tracer().take_frames().is_empty());
+
+    let mut last = Emulator::<Memory, DiskMemory, _>::with_tracer(
+        Default::default(),
+        Default::default(),
+        ScreenCapture::last_frame(2..5),
+    );
+    last.memory_mut()[..code.len()].copy_from_slice(&code);
+    last.run(last.config().screen_refresh_time * 8 + 100);
+    assert_eq!(last.tracer().take_frames().iter().map(|frame| frame[0][0]).collect::<Vec<_>>(), [4]);
+
+    let mut image = Vec::new();
+    write_png(&frames[0], 3, &mut image).unwrap();
+    let mut reader = png::Decoder::new(&image[..]).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height), (SCREEN_WIDTH as u32 * 3, SCREEN_HEIGHT as u32 * 3));
+    assert_eq!(info.color_type, png::ColorType::Indexed);
+    assert_eq!(pixels[..4], [2, 2, 2, 0]);
+    assert_eq!(pixels[info.line_size * 2..][..4], [2, 2, 2, 0]);
+    assert_eq!(pixels[info.line_size * 3..][..4], [0, 0, 0, 0]);
+
+    let mut animation = Vec::new();
+    write_gif(&frames, 2, 5, &mut animation).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(&animation[..]).unwrap();
+    let mut delays = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!((frame.width, frame.height), (SCREEN_WIDTH as u16 * 2, SCREEN_HEIGHT as u16 * 2));
+        delays.push((frame.delay, frame.buffer[0]));
+    }
+    assert_eq!(delays, [(5, 2), (5, 3), (5, 4)]);
+
+    assert!(write_png(&frames[0], 0, &mut Vec::new()).is_err());
+}
+
+#[test]
+fn disk_image() {
+    let mut image = DiskImage::from_raw(&[1, 2, 3]).unwrap();
+    image.label = "boot disk".to_owned();
+    image.read_only = true;
+    image.data_mut()[DISK_SIZE - 1] = 0xaa;
+
+    let raw = image.encode(DiskEncoding::Raw);
+    let sparse = image.encode(DiskEncoding::Sparse);
+    assert!(raw.len() > DISK_SIZE);
+    assert!(sparse.len() < 1024);
+    for bytes in &[&raw, &sparse] {
+        let decoded = DiskImage::load(bytes).unwrap();
+        assert_eq!(decoded.label, "boot disk");
+        assert!(decoded.read_only);
+        assert!(decoded.data()[..] == image.data()[..]);
+    }
+
+    let loaded = DiskImage::load(&[0x5d, 0x00]).unwrap();
+    assert_eq!(loaded.label, "");
+    assert!(!loaded.read_only);
+    assert_eq!(loaded.data()[..3], [0x5d, 0x00, 0x00]);
+    assert_eq!(DiskImage::from_raw(&vec![0; DISK_SIZE + 1]).err(), Some(DiskImageError::TooLarge));
+
+    let mut corrupted = sparse.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert_eq!(DiskImage::decode(&corrupted).err(), Some(DiskImageError::ChecksumMismatch));
+    assert_eq!(DiskImage::decode(&sparse[..sparse.len() - 1]).err(), Some(DiskImageError::Truncated));
+    assert_eq!(DiskImage::decode(&raw[..DISK_SIZE]).err(), Some(DiskImageError::Truncated));
+    assert_eq!(DiskImage::decode(b"TCPUSNAP").err(), Some(DiskImageError::BadMagic));
+    let mut newer = sparse;
+    newer[8] = 2;
+    assert_eq!(DiskImage::decode(&newer).err(), Some(DiskImageError::UnsupportedVersion(2)));
+}
+
+#[test]
+fn overlay_disk() {
+    let code = [
+        0xf8, 0x8e, 0x00, 0x01, // write0 0, 0x100
+        0x02, // wait
+        0x04, // halt
+    ];
+    let mut base = DiskMemory::default();
+    base.as_mut()[..code.len()].copy_from_slice(&code);
+    let base = std::sync::Arc::new(base);
+    for _ in 0..2 {
+        let mut emulator = Emulator::<Memory, OverlayDisk>::new();
+        *emulator.disk_slot(DiskId::D0) = OverlayDisk::new(base.clone());
+        emulator.insert_disk(DiskId::D0);
+        emulator.reset();
+        emulator.run(200_000);
+        assert!(!emulator.is_running());
+
+        // the write covers 4 KiB, which are all reported though most are zeros as on the base
+        assert!(!emulator.disk_storage(DiskId::D0).unwrap().written_blocks().is_empty());
+        let snapshot = emulator.snapshot();
+        emulator.restore(&snapshot).unwrap();
+        let overlay = std::mem::take(emulator.disk_slot(DiskId::D0));
+        assert_eq!(overlay.written_blocks(), (0x100..0x200).collect::<Vec<_>>());
+        let delta = overlay.delta();
+        assert_eq!(delta[0].1[..code.len()], code);
+        assert_eq!(delta[1].1, [0; DISK_BLOCK_SIZE]);
+        assert_eq!(Storage::as_ref(&*base)[0x1000], 0);
+
+        let mut copy = OverlayDisk::new(base.clone());
+        copy.apply_delta(&delta);
+        assert!(copy.as_ref()[..] == overlay.as_ref()[..]);
+        assert_eq!(copy.written_blocks(), overlay.written_blocks());
+        copy.discard();
+        assert!(copy.delta().is_empty());
+        assert!(copy.as_ref()[..] == Storage::as_ref(&*base)[..]);
+
+        // writes that cross pages, and writes after the contents were built in one piece
+        let mut bytes = [0; 40];
+        copy.write(0x1ff0, &[7; 40]);
+        copy.read(0x1ff0, &mut bytes);
+        assert_eq!(bytes, [7; 40]);
+        assert_eq!(copy.written_blocks(), [0x1ff, 0x200, 0x201]);
+        assert_eq!(copy.as_ref()[0x2017], 7);
+        copy.write(0x3000, &[8]);
+        assert_eq!(copy.as_ref()[0x3000], 8);
+        assert_eq!(copy.written_blocks(), [0x1ff, 0x200, 0x201, 0x300]);
+        copy.as_mut();
+        assert_eq!(copy.written_blocks().len(), DISK_SIZE / DISK_BLOCK_SIZE);
+
+        let merged = overlay.merge();
+        assert_eq!(merged.as_ref()[..code.len()], code);
+        assert_eq!(merged.as_ref()[0x1000..][..code.len()], code);
+    }
+}
+
+#[test]
+fn snapshot_mid_disk_transfer() {
+    let mut original = emulator();
+    let code = [
+        0xf1, 0xe8, 0x00, 0x20, // read1 0x2000, 0
+        0x02, // wait
+        0x5d, 0x04, // jmp 4
+    ];
+    original.memory_mut()[..code.len()].copy_from_slice(&code);
+    for (i, byte) in original.disk_slot(DiskId::D1).as_mut().iter_mut().enumerate() {
+        *byte = (i * 7) as u8;
+    }
+    original.insert_disk(DiskId::D1);
+    original.run(1000);
+    let snapshot = original.snapshot();
+
+    let mut restored = emulator();
+    restored.restore(&snapshot).unwrap();
+    original.run(200_000);
+    restored.run(200_000);
+
+    let mut expected = metrohash::MetroHash::default();
+    let mut actual = metrohash::MetroHash::default();
+    hash_state(&original, &mut expected);
+    hash_state(&restored, &mut actual);
+    assert_eq!(expected.finish(), actual.finish());
+    assert_eq!(original.cycles(), restored.cycles());
+    assert_eq!(original.memory()[0x2000..0x3000], restored.disk_slot(DiskId::D1).as_ref()[..0x1000]);
+
+    assert_eq!(restored.restore(&snapshot[..100]), Err(SnapshotError::Truncated));
+    assert_eq!(restored.restore(b"not a snapshot"), Err(SnapshotError::BadMagic));
+
+    // time to the next screen refresh, then timer cycles remaining
+    for offset in [70, 78] {
+        let mut corrupted = snapshot.clone();
+        corrupted[offset..(offset + 8)].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(restored.restore(&corrupted), Err(SnapshotError::Invalid));
+    }
+    let mut valid = snapshot.clone();
+    valid[78..86].copy_from_slice(&(0x10000u64 << 15).to_le_bytes());
+    restored.restore(&valid).unwrap();
+}
+
+#[test]
+fn step_back() {
+    let code = [
+        0xf1, 0xe8, 0x00, 0x20, // read1 0x2000, 0
+        0x02, // wait
+        0x98, 0x1e, 0x00, 0x20, // store b, 0x2000
+        0x5d, 0x04, // jmp 4
+    ];
+    let mut emulator = emulator();
+    emulator.memory_mut()[..code.len()].copy_from_slice(&code);
+    for (i, byte) in emulator.disk_slot(DiskId::D1).as_mut().iter_mut().enumerate() {
+        *byte = (i * 7) as u8;
+    }
+    emulator.insert_disk(DiskId::D1);
+    emulator.enable_history(200_000);
+    emulator.run(1000);
+    let snapshot = emulator.snapshot();
+
+    emulator.run(150_000);
+    emulator.key_down(0x1234);
+    emulator.run(100);
+    assert_eq!(emulator.memory()[0x2000], 0x34);
+    assert!(emulator.run_back_to(5));
+    assert_eq!(emulator.memory()[0x2000], 0);
+    emulator.cycle();
+    assert_eq!(emulator.memory()[0x2000], 0x34);
+    emulator.step_back(1);
+
+    let remaining = emulator.history_len();
+    assert_eq!(emulator.step_back(remaining + 10), remaining);
+    assert_eq!(emulator.cycles(), 0);
+    emulator.run(1000);
+    assert_eq!(emulator.snapshot(), snapshot);
+}
+
+#[test]
+fn step_back_device_writes() {
+    // stores the number of cycles it was ticked
+    struct Stamper {
+        cycles: u8,
+    }
+
+    impl Device for Stamper {
+        fn contains(&self, _address: u16) -> bool {
+            false
+        }
+
+        fn tick(&mut self, events: &mut Events<'_>) {
+            self.cycles += 1;
+            events.store(0x2000, self.cycles);
+        }
+    }
+
+    let mut emulator = Emulator::<Memory, DiskMemory, _, _>::with_devices(
+        Default::default(),
+        Default::default(),
+        NoopTracer,
+        Stamper { cycles: 0 },
+    );
+    emulator.memory_mut()[..2].copy_from_slice(&[0x5d, 0x00]); // jmp 0
+    emulator.enable_history(100);
+    emulator.run(10);
+    assert_eq!(emulator.memory()[0x2000], 10);
+    assert_eq!(emulator.step_back(4), 4);
+    assert_eq!(emulator.memory()[0x2000], 6);
+    assert_eq!(emulator.step_back(6), 6);
+    assert_eq!(emulator.memory()[0x2000], 0);
+}
+
+#[test]
+fn replay_recorded_inputs() {
+    let code = [
+        0x80, 0x4e, 0x00, 0x10, // mov i, 0x1000
+        0x02, // wait
+        0x98, 0x14, // store b, i
+        0x81, 0x49, // add i, 1
+        0x5d, 0x04, // jmp 4
+    ];
+    let mut recorder = Recorder::new(emulator());
+    recorder.emulator_mut().disk_slot(DiskId::D0).as_mut()[..code.len()].copy_from_slice(&code);
+    recorder.insert_disk(DiskId::D0);
+    recorder.reset();
+    recorder.run(500);
+    recorder.key_down(65);
+    recorder.run(300);
+    recorder.key_up(65);
+    recorder.key_down(66);
+    recorder.run(100_000);
+    recorder.key_up(66);
+    recorder.run(1000);
+    let (original, log) = recorder.into_parts();
+    assert_eq!(log.inputs()[2], (500, Input::KeyDown(65)));
+    assert_eq!(log.end(), original.cycles());
+
+    let log = InputLog::decode(&log.encode()).unwrap();
+    let mut replayed = emulator();
+    log.replay(&mut replayed).unwrap();
+
+    let mut expected = metrohash::MetroHash::default();
+    let mut actual = metrohash::MetroHash::default();
+    hash_state(&original, &mut expected);
+    hash_state(&replayed, &mut actual);
+    assert_eq!(expected.finish(), actual.finish());
+    assert_eq!(&replayed.memory()[0x1000..0x1005], &[65, 65, 66, 0, 66]);
+
+    // a log naming a drive that isn't connected
+    let mut recorder = Recorder::new(emulator());
+    recorder.remove_disk(DiskId::D1);
+    let mut bytes = recorder.log().encode();
+    // the drive index comes right before the end cycle
+    let index = bytes.len() - 9;
+    bytes[index] = 2;
+    let log = InputLog::decode(&bytes).unwrap();
+    assert_eq!(log.inputs()[0].1, Input::RemoveDisk(DiskId::D2));
+    assert_eq!(log.replay(&mut emulator()), Err(ReplayError::Invalid));
+}
+
 #[test]
 fn full_random() {
     let seed = 0xcafe_babe_dead_beef_u64.to_le_bytes();
@@ -495,10 +1453,10 @@ fn full_random() {
         0x549d15cd80f4d1db, 0xbf38028f4b23319a, 0x08759cc3349a105a, 0xe0a5706efe5074f9,
         0xd30b58f69c92c688, 0x9b95cb6440b2182e, 0x094c4545083dd8fb, 0xf96d776b9a2c6ed8,
         0xb5f2ef48e0971245, 0xe616b65118f83269, 0x3deb495f300e843d, 0xa44770b710735ea2,
-        0xa223e2f4bf1ffa75, 0x2a94d9744ac7fea3, 0xeb6b6def1ab2c408, 0x21623bf4cef258d1,
-        0x730080dab3883c99, 0x3a8e66d68cc5cec2, 0x56c3391ea9e7804c, 0x199efc47458d12df,
-        0x5bcce1012a11c652, 0x6f623165ff5223cf, 0x438b5e7fa297c098, 0x66b0a920beb81398,
-        0xefa04a04d55f8709, 0x7db95736aadc8b51, 0xe90a44554ea7b117, 0xc95509f5c9c32326,
+        0xa223e2f4bf1ffa75, 0x2a94d9744ac7fea3, 0xf85822b5306934e4, 0xb0ff7d4e03c8ee33,
+        0xbbe989550e32afa6, 0xee116288068a34b0, 0x772d336402bae7a8, 0xb6e59c7a5aed70f9,
+        0x5bcce1012a11c652, 0x6f623165ff5223cf, 0x6d3a9825f7ed4e41, 0x2622d3b3947e90a0,
+        0x4dc8ce575b3d39fd, 0xbc9d864ae7b22008, 0x6bbb94b1a8c02c78, 0x6de34150b90ef374,
     ];
 
     for (b, (&actual, &expected)) in hashes.iter().zip(expected_hashes.iter()).enumerate() {
@@ -0,0 +1,85 @@
+use super::{Device, Event, Events, MEMORY_SIZE};
+
+pub(crate) const TIMER_POSITION: u16 = 0xcc00;
+const PERIOD: u16 = TIMER_POSITION;
+const CONTROL: u16 = TIMER_POSITION + 2;
+
+const MODE_MASK: u8 = 0b11;
+const MODE_ONE_SHOT: u8 = 1;
+const MODE_REPEATING: u8 = 2;
+
+/// Longest interval, the largest period with the largest prescaler.
+#[cfg(any(test, feature = "std"))]
+pub(crate) const MAX_INTERVAL: u64 = 0x10000 << 15;
+
+/// Interval timer, see the timer section in `arch.txt`. Its registers are
+/// kept in RAM, the device only tracks the cycles left until it fires.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Timer {
+    // 0 when the timer is stopped
+    pub(crate) remaining: u64,
+}
+
+impl Timer {
+    pub(crate) const fn new() -> Self {
+        Timer { remaining: 0 }
+    }
+
+    fn interval(memory: &[u8; MEMORY_SIZE]) -> u64 {
+        let period = u16::from_le_bytes([
+            memory[usize::from(PERIOD)],
+            memory[usize::from(PERIOD + 1)],
+        ]);
+        let period = if period == 0 { 0x10000 } else { u64::from(period) };
+        let prescaler = memory[usize::from(CONTROL)] >> 4;
+        period << prescaler
+    }
+}
+
+impl Device for Timer {
+    fn contains(&self, address: u16) -> bool {
+        (PERIOD..=CONTROL).contains(&address)
+    }
+
+    fn store(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        memory[usize::from(address)] = value;
+        if address == CONTROL {
+            self.remaining = match value & MODE_MASK {
+                MODE_ONE_SHOT | MODE_REPEATING => Timer::interval(memory),
+                _ => 0,
+            };
+        }
+    }
+
+    fn tick(&mut self, events: &mut Events<'_>) {
+        if self.remaining == 0 {
+            return;
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            events.push(Event::timer());
+            let memory = events.memory();
+            if memory[usize::from(CONTROL)] & MODE_MASK == MODE_REPEATING {
+                self.remaining = Timer::interval(memory);
+            }
+        }
+    }
+
+    fn idle_cycles(&self) -> u64 {
+        if self.remaining == 0 {
+            u64::MAX
+        } else {
+            self.remaining - 1
+        }
+    }
+
+    fn skip(&mut self, _memory: &[u8; MEMORY_SIZE], cycles: u64) {
+        if self.remaining != 0 {
+            self.remaining -= cycles;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Timer::new();
+    }
+}
@@ -1,7 +1,6 @@
 const diskSize = 1 << 20;
-const processorRate = 1024 * 1024 * 1.5;
 // active disk light remains lit for 0.5 s after disk became idle
-const diskActiveLightTime = processorRate / 2;
+const diskActiveLightTime = 0.5;
 
 class Disk {
     public id: string;
@@ -48,6 +47,7 @@ class Emulator {
     private wasm: WebAssembly.Instance;
     private keys: {[key: string]: boolean};
     private timeBudget: number;
+    private processorRate: number;
     public slots: (InsertedDisk | null)[];
 
     constructor(wasm: WebAssembly.Instance) {
@@ -56,6 +56,7 @@ class Emulator {
         this.slots = [null, null];
         this.timeBudget = 0;
         (this.wasm.exports.initialize as any)();
+        this.processorRate = (this.wasm.exports.clock_rate as any)();
     }
 
     public reset() {
@@ -64,8 +65,8 @@ class Emulator {
 
     public run(dt: number) {
         this.timeBudget += dt;
-        const cycles = Math.floor(this.timeBudget * processorRate);
-        const cycleTime = cycles / processorRate;
+        const cycles = Math.floor(this.timeBudget * this.processorRate);
+        const cycleTime = cycles / this.processorRate;
         this.timeBudget -= cycleTime;
         (this.wasm.exports.run as any)(cycles);
         for (let disk = 0; disk < 2; disk++) {
@@ -73,9 +74,9 @@ class Emulator {
             if (slot !== null) {
                 const diskStatus = (this.wasm.exports.disk_stats as any)(disk);
                 const modified = ((diskStatus >> 1) & 1) !== 0;
-                const idleTime = diskStatus >> 2;
+                const idleTime = diskStatus >> 3;
                 slot.modified = modified;
-                slot.working = idleTime <= diskActiveLightTime;
+                slot.working = idleTime <= diskActiveLightTime * this.processorRate;
             }
         }
     }
@@ -173,6 +174,7 @@ class Emulator {
     }
 }
 
+// tcpu/src/palette.rs has the same colors for native tools
 function getColor(index: number): { r: number, g: number, b: number } {
     function toRgb(h: number, s: number, v: number) {
         while (h < 0) h += 360;